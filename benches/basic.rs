@@ -16,6 +16,7 @@ extern crate unicode_bidi;
 use test::Bencher;
 
 use unicode_bidi::BidiInfo;
+use unicode_bidi::format_chars::is_explicit_format_char;
 
 
 const LTR_TEXTS: &[&str] = &["abc\ndef\nghi", "abc 123\ndef 456\nghi 789"];
@@ -60,3 +61,15 @@ fn bench_3_reorder_line_for_ltr_texts(b: &mut Bencher) {
 fn bench_4_reorder_line_for_bidi_texts(b: &mut Bencher) {
     bench_reorder_line(b, BIDI_TEXTS);
 }
+
+#[bench]
+fn bench_5_bidi_info_new_for_single_class_text(b: &mut Bencher) {
+    let text = "א".repeat(100_000);
+    b.iter(|| { BidiInfo::new(&text, None); });
+}
+
+#[bench]
+fn bench_6_is_explicit_format_char(b: &mut Bencher) {
+    let text = "abc \u{2067}אבג\u{2069} \u{202B}123\u{202C} \u{200F}".repeat(1_000);
+    b.iter(|| text.chars().filter(|&c| is_explicit_format_char(c)).count());
+}
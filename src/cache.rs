@@ -0,0 +1,131 @@
+// Copyright 2017 The Servo Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Memoized analysis of repeated texts
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use super::{BidiInfoBuf, Level};
+
+/// A cache of the bidi analysis of texts, keyed by their content.
+///
+/// This is useful when the same texts are analyzed many times, e.g. the lines of a log viewer.
+/// The analyses are shared, so a repeated text returns the same `Rc` as the first time.  The cache
+/// grows with each distinct text, until `clear()` is called.
+///
+/// ```rust
+/// use std::rc::Rc;
+/// use unicode_bidi::BidiCache;
+///
+/// let mut cache = BidiCache::new(None);
+/// let first = cache.analyze("abc אבג");
+/// let second = cache.analyze("abc אבג");
+/// assert!(Rc::ptr_eq(&first, &second));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BidiCache {
+    default_para_level: Option<Level>,
+    analyses: HashSet<Entry>,
+}
+
+/// An analysis in the cache, compared and hashed by its text so that it can be looked up with a
+/// `&str` without storing the text twice.
+#[derive(Clone, Debug)]
+struct Entry(Rc<BidiInfoBuf>);
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.0.text == other.0.text
+    }
+}
+
+impl Eq for Entry {}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.text.hash(state);
+    }
+}
+
+impl Borrow<str> for Entry {
+    fn borrow(&self) -> &str {
+        &self.0.text
+    }
+}
+
+impl BidiCache {
+    /// An empty cache, which analyzes each text with the paragraph level `default_para_level`
+    /// like `BidiInfo::new()`.
+    pub fn new(default_para_level: Option<Level>) -> BidiCache {
+        BidiCache {
+            default_para_level,
+            analyses: HashSet::new(),
+        }
+    }
+
+    /// The analysis of `text`, which is only computed if the same text wasn't analyzed before.
+    pub fn analyze(&mut self, text: &str) -> Rc<BidiInfoBuf> {
+        if let Some(entry) = self.analyses.get(text) {
+            return entry.0.clone();
+        }
+        let analysis = Rc::new(BidiInfoBuf::new(text.to_owned(), self.default_para_level));
+        self.analyses.insert(Entry(analysis.clone()));
+        analysis
+    }
+
+    /// The number of distinct texts in the cache.
+    pub fn len(&self) -> usize {
+        self.analyses.len()
+    }
+
+    /// If the cache holds no analysis.
+    pub fn is_empty(&self) -> bool {
+        self.analyses.is_empty()
+    }
+
+    /// Remove all analyses from the cache.  Those still in use elsewhere are not affected.
+    pub fn clear(&mut self) {
+        self.analyses.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{BidiInfo, RTL_LEVEL};
+
+    #[test]
+    fn test_bidi_cache() {
+        let mut cache = BidiCache::new(None);
+        assert!(cache.is_empty());
+
+        let first = cache.analyze("abc אבג");
+        assert_eq!(first.bidi_info(), BidiInfo::new("abc אבג", None));
+        let second = cache.analyze("abc אבג");
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+
+        let other = cache.analyze("אבג abc");
+        assert!(!Rc::ptr_eq(&first, &other));
+        assert_eq!(cache.len(), 2);
+
+        // Cleared analyses are computed again.
+        cache.clear();
+        assert!(cache.is_empty());
+        let third = cache.analyze("abc אבג");
+        assert!(!Rc::ptr_eq(&first, &third));
+        assert_eq!(first, third);
+
+        let mut cache = BidiCache::new(Some(RTL_LEVEL));
+        assert_eq!(cache.analyze("abc").paragraphs[0].level, RTL_LEVEL);
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2017 The Servo Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sets of `BidiClass` values
+
+use super::BidiClass;
+use super::BidiClass::*;
+
+/// A set of `BidiClass` values, stored as a bitmask keyed by `BidiClass as u32`.
+///
+/// The algorithm repeatedly tests whether a class belongs to a fixed set, such as the classes
+/// removed by rule X9.  The sets it uses are available as associated constants.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BidiClassSet(u32);
+
+impl BidiClassSet {
+    /// Classes removed by rule X9: `RLE`, `LRE`, `RLO`, `LRO`, `PDF` and `BN`.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#X9>
+    pub const REMOVED_BY_X9: BidiClassSet = BidiClassSet::empty()
+        .with(RLE)
+        .with(LRE)
+        .with(RLO)
+        .with(LRO)
+        .with(PDF)
+        .with(BN);
+
+    /// Strong classes: `L`, `R` and `AL`.
+    pub const STRONG: BidiClassSet = BidiClassSet::empty().with(L).with(R).with(AL);
+
+    /// Weak classes, the input of rules W1-W7: `EN`, `ES`, `ET`, `AN`, `CS`, `NSM` and `BN`.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#Resolving_Weak_Types>
+    pub const WEAK: BidiClassSet = BidiClassSet::empty()
+        .with(EN)
+        .with(ES)
+        .with(ET)
+        .with(AN)
+        .with(CS)
+        .with(NSM)
+        .with(BN);
+
+    /// Neutral or Isolate formatting classes (NI), the input of rules N1 and N2: `B`, `S`, `WS`,
+    /// `ON`, `FSI`, `LRI`, `RLI` and `PDI`.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#NI>
+    pub const NEUTRAL_OR_ISOLATE: BidiClassSet = BidiClassSet::empty()
+        .with(B)
+        .with(S)
+        .with(WS)
+        .with(ON)
+        .with(FSI)
+        .with(LRI)
+        .with(RLI)
+        .with(PDI);
+
+    /// Isolate initiators: `LRI`, `RLI` and `FSI`.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#BD8>
+    pub const ISOLATE_INITIATORS: BidiClassSet =
+        BidiClassSet::empty().with(LRI).with(RLI).with(FSI);
+
+    /// The empty set.
+    #[inline]
+    pub const fn empty() -> BidiClassSet {
+        BidiClassSet(0)
+    }
+
+    /// This set, with `class` added.
+    #[inline]
+    pub const fn with(self, class: BidiClass) -> BidiClassSet {
+        BidiClassSet(self.0 | 1 << class as u32)
+    }
+
+    /// All classes that are in this set or in `other`.
+    #[inline]
+    pub const fn union(self, other: BidiClassSet) -> BidiClassSet {
+        BidiClassSet(self.0 | other.0)
+    }
+
+    /// If `class` is in this set.
+    #[inline]
+    pub const fn contains(self, class: BidiClass) -> bool {
+        self.0 & 1 << class as u32 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::BIDI_CLASSES;
+
+    fn members(set: BidiClassSet) -> Vec<BidiClass> {
+        BIDI_CLASSES.iter().cloned().filter(|&c| set.contains(c)).collect()
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(members(BidiClassSet::empty()), vec![]);
+        assert_eq!(BidiClassSet::default(), BidiClassSet::empty());
+    }
+
+    #[test]
+    fn test_with_and_union() {
+        let set = BidiClassSet::empty().with(L).with(R);
+        assert_eq!(members(set), vec![L, R]);
+        assert_eq!(members(set.with(L)), vec![L, R]);
+        assert_eq!(
+            members(set.union(BidiClassSet::empty().with(AL))),
+            vec![AL, L, R]
+        );
+    }
+
+    #[test]
+    fn test_predefined_sets() {
+        assert_eq!(
+            members(BidiClassSet::REMOVED_BY_X9),
+            vec![BN, LRE, LRO, PDF, RLE, RLO]
+        );
+        assert_eq!(members(BidiClassSet::STRONG), vec![AL, L, R]);
+        assert_eq!(
+            members(BidiClassSet::WEAK),
+            vec![AN, BN, CS, EN, ES, ET, NSM]
+        );
+        assert_eq!(
+            members(BidiClassSet::NEUTRAL_OR_ISOLATE),
+            vec![B, FSI, LRI, ON, PDI, RLI, S, WS]
+        );
+        assert_eq!(members(BidiClassSet::ISOLATE_INITIATORS), vec![FSI, LRI, RLI]);
+    }
+}
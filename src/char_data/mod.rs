@@ -10,19 +10,61 @@
 //! Accessor for `Bidi_Class` property from Unicode Character Database (UCD)
 include!(concat!(env!("OUT_DIR"), "/bidi_class.rs")); // generated by build.rs
 
+mod class_set;
+mod mirroring;
 mod tables;
 
 const MASK: usize = BLOCK_SIZE - 1;
 const SHIFT: usize = MASK.count_ones() as usize;
 
+pub use self::class_set::BidiClassSet;
 pub use self::tables::BidiClass;
 
 use std::char;
+use std::convert::TryFrom;
+use std::str::FromStr;
+#[cfg(feature = "simd")]
+use std::iter::repeat;
+#[cfg(feature = "simd")]
+use std::str;
 
 use BidiClass::*;
 
+/// A Unicode version number: major, minor and update.
+pub type UnicodeVersion = (u64, u64, u64);
+
 /// The [Unicode version](http://www.unicode.org/versions/) of data
-pub const UNICODE_VERSION: (u64, u64, u64) = (12, 1, 0);
+pub const UNICODE_VERSION: UnicodeVersion = (12, 1, 0);
+
+/// A source of `Bidi_Class` property values.
+///
+/// The data built into this crate is available as `HardcodedBidiData`.  Other sources can be
+/// used to run the algorithm with a different version of the Unicode Character Database.
+pub trait BidiDataSource {
+    /// The `BidiClass` of a single char.
+    fn bidi_class(&self, c: char) -> BidiClass;
+
+    /// The Unicode version of the data, if known.
+    ///
+    /// This is `None` by default, since `UNICODE_VERSION` only describes the built-in data.
+    fn unicode_version(&self) -> Option<UnicodeVersion> {
+        None
+    }
+}
+
+/// The `Bidi_Class` data built into this crate, of version `UNICODE_VERSION`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HardcodedBidiData;
+
+impl BidiDataSource for HardcodedBidiData {
+    fn bidi_class(&self, c: char) -> BidiClass {
+        bidi_class(c)
+    }
+
+    fn unicode_version(&self) -> Option<UnicodeVersion> {
+        Some(UNICODE_VERSION)
+    }
+}
 
 /// Find the `BidiClass` of a single char.
 pub fn bidi_class(c: char) -> BidiClass {
@@ -38,6 +80,225 @@ pub fn bidi_class(c: char) -> BidiClass {
     }
 }
 
+/// The ranges of `Default_Ignorable_Code_Point` characters, from DerivedCoreProperties.txt of the
+/// same version of the Unicode Character Database as the `Bidi_Class` data.
+const DEFAULT_IGNORABLE_RANGES: &[(char, char)] = &[
+    ('\u{00AD}', '\u{00AD}'), ('\u{034F}', '\u{034F}'), ('\u{061C}', '\u{061C}'),
+    ('\u{115F}', '\u{1160}'), ('\u{17B4}', '\u{17B5}'), ('\u{180B}', '\u{180E}'),
+    ('\u{200B}', '\u{200F}'), ('\u{202A}', '\u{202E}'), ('\u{2060}', '\u{206F}'),
+    ('\u{3164}', '\u{3164}'), ('\u{FE00}', '\u{FE0F}'), ('\u{FEFF}', '\u{FEFF}'),
+    ('\u{FFA0}', '\u{FFA0}'), ('\u{FFF0}', '\u{FFF8}'), ('\u{1BCA0}', '\u{1BCA3}'),
+    ('\u{1D173}', '\u{1D17A}'), ('\u{E0000}', '\u{E0FFF}'),
+];
+
+/// If `c` is a default ignorable code point of class `BN`, e.g. U+200B ZERO WIDTH SPACE, U+00AD
+/// SOFT HYPHEN, U+2060 WORD JOINER or U+FEFF ZERO WIDTH NO-BREAK SPACE.
+///
+/// Such characters have no visible effect, and rule X9 removes them from the algorithm like the
+/// other `BN` characters, so they don't split level runs.  The control characters of class `BN`
+/// are not default ignorable, and neither are the default ignorable characters of other classes,
+/// like the directional formatting characters and the variation selectors (`NSM`).
+///
+/// <http://www.unicode.org/reports/tr9/#X9>
+pub fn is_default_ignorable_bn(c: char) -> bool {
+    bidi_class(c) == BN &&
+        DEFAULT_IGNORABLE_RANGES
+            .iter()
+            .any(|&(start, end)| start <= c && c <= end)
+}
+
+/// The `Bidi_Mirroring_Glyph` of a single char: the char whose glyph is the mirror image of the
+/// glyph of `c`, if there is one.
+///
+/// Renderers display such a char instead of `c` when `c` resolves to an odd (RTL) level (rule L4),
+/// e.g. `)` instead of `(`.  Some chars are mirrored without such a pair, in which case this
+/// returns `None`.
+///
+/// <http://www.unicode.org/reports/tr9/#L4>
+pub fn bidi_mirroring_glyph(c: char) -> Option<char> {
+    mirroring::BIDI_MIRRORING_GLYPH
+        .binary_search_by_key(&c, |&(c, _)| c)
+        .ok()
+        .map(|i| mirroring::BIDI_MIRRORING_GLYPH[i].1)
+}
+
+/// The `BidiClass` of each ASCII character.
+#[cfg(feature = "simd")]
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const ASCII_CLASSES: [BidiClass; 128] = [
+    // U+0000..U+001F
+    BN, BN, BN, BN, BN, BN, BN, BN, BN, S, B, S, WS, B, BN, BN,
+    BN, BN, BN, BN, BN, BN, BN, BN, BN, BN, BN, BN, B, B, B, S,
+    // U+0020..U+003F
+    WS, ON, ON, ET, ET, ET, ON, ON, ON, ON, ON, ES, CS, ES, CS, CS,
+    EN, EN, EN, EN, EN, EN, EN, EN, EN, EN, CS, ON, ON, ON, ON, ON,
+    // U+0040..U+005F
+    ON, L, L, L, L, L, L, L, L, L, L, L, L, L, L, L,
+    L, L, L, L, L, L, L, L, L, L, L, ON, ON, ON, ON, ON,
+    // U+0060..U+007F
+    ON, L, L, L, L, L, L, L, L, L, L, L, L, L, L, L,
+    L, L, L, L, L, L, L, L, L, L, L, ON, ON, ON, ON, BN,
+];
+
+/// Find the `BidiClass` of each byte of UTF-8 text, quickly for ASCII text.
+///
+/// The classes are appended to `out`, one per byte as in `InitialInfo::original_classes`: if a
+/// character is multiple bytes wide, its class appears multiple times.  The leading ASCII bytes are
+/// found eight at a time, by checking the high bit of each byte of a `u64` ("SIMD within a
+/// register", since this crate doesn't use `unsafe` platform intrinsics), and classified with a
+/// table.  From the first non-ASCII byte on, each character is classified with `bidi_class()`.
+///
+/// Panics if `bytes` is not valid UTF-8.
+#[cfg(feature = "simd")]
+pub fn bidi_classes_ascii_fast(bytes: &[u8], out: &mut Vec<BidiClass>) {
+    let ascii_len = ascii_prefix_len(bytes);
+    out.reserve(bytes.len());
+    out.extend(bytes[..ascii_len].iter().map(|&b| ASCII_CLASSES[b as usize]));
+
+    let rest = str::from_utf8(&bytes[ascii_len..]).expect("Invalid UTF-8");
+    for c in rest.chars() {
+        out.extend(repeat(bidi_class(c)).take(c.len_utf8()));
+    }
+}
+
+/// The number of ASCII bytes at the start of `bytes`.
+#[cfg(feature = "simd")]
+fn ascii_prefix_len(bytes: &[u8]) -> usize {
+    const HIGH_BITS: u64 = 0x8080_8080_8080_8080;
+
+    let mut len = 0;
+    for chunk in bytes.chunks_exact(8) {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        if u64::from_le_bytes(word) & HIGH_BITS != 0 {
+            break;
+        }
+        len += 8;
+    }
+    len + bytes[len..].iter().take_while(|&&b| b < 0x80).count()
+}
+
+/// All `BidiClass` values, ordered by their `index()`.
+const BIDI_CLASSES: [BidiClass; 23] = [
+    AL, AN, B, BN, CS, EN, ES, ET, FSI, L, LRE, LRI, LRO, NSM, ON, PDF, PDI, R, RLE, RLI, RLO, S,
+    WS,
+];
+
+/// The short names of all `BidiClass` values, ordered by their `index()`.
+const BIDI_CLASS_NAMES: [&str; 23] = [
+    "AL", "AN", "B", "BN", "CS", "EN", "ES", "ET", "FSI", "L", "LRE", "LRI", "LRO", "NSM", "ON",
+    "PDF", "PDI", "R", "RLE", "RLI", "RLO", "S", "WS",
+];
+
+impl BidiClass {
+    /// A stable index for this class, in `0..23`.
+    ///
+    /// Classes are numbered in alphabetical order of their short names: `AL` is 0, `AN` is 1, `B`
+    /// is 2, and so on up to `WS`, which is 22.  This can be used to look up per-class values in a
+    /// `[T; 23]` array instead of matching on the class.
+    #[inline]
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The class with the given `index()`, or `None` if `index` is not in `0..23`.
+    #[inline]
+    pub fn from_index(index: usize) -> Option<BidiClass> {
+        BIDI_CLASSES.get(index).cloned()
+    }
+
+    /// The short name of this class in the Unicode Character Database, e.g. `"AL"`.
+    pub fn short_name(self) -> &'static str {
+        BIDI_CLASS_NAMES[self.index()]
+    }
+
+    /// If characters of this class are typically displayed upright in vertical text.
+    ///
+    /// This is only a heuristic, since orientation is not part of the bidi algorithm: it returns
+    /// `true` for `L`, which covers CJK ideographs and kana, and for the numbers `EN` and `AN`,
+    /// which vertical layout commonly keeps upright.  The RTL classes `R` and `AL` are rotated,
+    /// and all remaining classes (separators, neutrals, formatting characters) are expected to
+    /// follow the orientation of the surrounding text, so it returns `false` for them.
+    pub fn prefers_upright(self) -> bool {
+        matches!(self, L | EN | AN)
+    }
+
+    /// If this is `ET`, which rule W5 changes to `EN` next to a European number.
+    pub fn is_european_number_terminator(self) -> bool {
+        self == ET
+    }
+
+    /// If this is one of the number separators `ES` and `CS`, which rule W4 resolves between two
+    /// numbers.
+    ///
+    /// Note that this does not include the segment and paragraph separators `S` and `B`.
+    pub fn is_separator(self) -> bool {
+        matches!(self, ES | CS)
+    }
+
+    /// If this is one of the number classes `EN` and `AN`.
+    pub fn is_number(self) -> bool {
+        matches!(self, EN | AN)
+    }
+}
+
+/// Error returned when converting a number that is not a valid `BidiClass::index()` to a
+/// `BidiClass`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidBidiClassIndex(pub u8);
+
+impl TryFrom<u8> for BidiClass {
+    type Error = InvalidBidiClassIndex;
+
+    /// The class with the given `index()`, fail if `index` is not in `0..23`.
+    #[inline]
+    fn try_from(index: u8) -> Result<BidiClass, InvalidBidiClassIndex> {
+        BidiClass::from_index(index as usize).ok_or(InvalidBidiClassIndex(index))
+    }
+}
+
+/// The number of characters of each `BidiClass` in `text`, indexed by `BidiClass::index()`.
+///
+/// Characters are counted once each, not once per byte, with their class from the Unicode
+/// Character Database: the class of an FSI is not resolved to LRI or RLI.
+pub fn bidi_class_histogram(text: &str) -> [u32; 23] {
+    let mut counts = [0; 23];
+    for c in text.chars() {
+        counts[bidi_class(c).index()] += 1;
+    }
+    counts
+}
+
+/// Error returned when parsing a string that is not the short name of a `BidiClass`, holding that
+/// string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BidiClassParseError(pub String);
+
+impl FromStr for BidiClass {
+    type Err = BidiClassParseError;
+
+    /// The class with the given short name, e.g. `"AL"`, as returned by `short_name()`.
+    fn from_str(s: &str) -> Result<BidiClass, BidiClassParseError> {
+        BIDI_CLASSES
+            .iter()
+            .cloned()
+            .find(|class| class.short_name() == s)
+            .ok_or_else(|| BidiClassParseError(s.to_owned()))
+    }
+}
+
+/// Parse a sequence of short class names separated by whitespace, e.g. `"L R AL EN"`, as used by
+/// the conformance test data and in test fixtures.
+pub fn parse_class_sequence(s: &str) -> Result<Vec<BidiClass>, BidiClassParseError> {
+    s.split_whitespace().map(str::parse).collect()
+}
+
+/// If `c` is in one of the Private Use Areas: U+E000..=U+F8FF, U+F0000..=U+FFFFD or
+/// U+100000..=U+10FFFD.
+pub fn is_private_use(c: char) -> bool {
+    matches!(c, '\u{E000}'..='\u{F8FF}' | '\u{F0000}'..='\u{FFFFD}' | '\u{100000}'..='\u{10FFFD}')
+}
+
 pub fn is_rtl(bidi_class: BidiClass) -> bool {
     match bidi_class {
         RLE | RLO | RLI => true,
@@ -49,6 +310,190 @@ pub fn is_rtl(bidi_class: BidiClass) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_index() {
+        assert_eq!(AL.index(), 0);
+        assert_eq!(L.index(), 9);
+        assert_eq!(WS.index(), 22);
+
+        let mut seen = [false; 23];
+        for &class in BIDI_CLASSES.iter() {
+            assert!(!seen[class.index()]);
+            seen[class.index()] = true;
+            assert_eq!(BidiClass::from_index(class.index()), Some(class));
+        }
+        assert!(seen.iter().all(|&x| x));
+
+        assert_eq!(BidiClass::from_index(23), None);
+        assert_eq!(BidiClass::from_index(::std::usize::MAX), None);
+    }
+
+    #[test]
+    fn test_data_source() {
+        struct AllRtl;
+        impl BidiDataSource for AllRtl {
+            fn bidi_class(&self, _: char) -> BidiClass {
+                R
+            }
+        }
+        struct Versioned;
+        impl BidiDataSource for Versioned {
+            fn bidi_class(&self, c: char) -> BidiClass {
+                bidi_class(c)
+            }
+            fn unicode_version(&self) -> Option<UnicodeVersion> {
+                Some((13, 0, 0))
+            }
+        }
+
+        assert_eq!(HardcodedBidiData.unicode_version(), Some((12, 1, 0)));
+        assert_eq!(HardcodedBidiData.bidi_class('a'), L);
+        assert_eq!(HardcodedBidiData.bidi_class('\u{05D0}'), R);
+        assert_eq!(AllRtl.unicode_version(), None);
+        assert_eq!(AllRtl.bidi_class('a'), R);
+        assert_eq!(Versioned.unicode_version(), Some((13, 0, 0)));
+    }
+
+    #[test]
+    fn test_try_from_u8() {
+        assert_eq!(BidiClass::try_from(0), Ok(AL));
+        assert_eq!(BidiClass::try_from(9), Ok(L));
+        assert_eq!(BidiClass::try_from(22), Ok(WS));
+        assert_eq!(BidiClass::try_from(23), Err(InvalidBidiClassIndex(23)));
+        assert_eq!(BidiClass::try_from(255), Err(InvalidBidiClassIndex(255)));
+    }
+
+    #[test]
+    fn test_is_default_ignorable_bn() {
+        for &c in &['\u{00AD}', '\u{180E}', '\u{200B}', '\u{200D}', '\u{2060}', '\u{206F}',
+                    '\u{FEFF}', '\u{1D173}', '\u{E0001}', '\u{E0FFF}'] {
+            assert!(is_default_ignorable_bn(c), "{:?}", c);
+        }
+        // Not default ignorable, or not BN.
+        for &c in &['a', ' ', '\u{0001}', '\u{007F}', '\u{034F}', '\u{061C}', '\u{200E}',
+                    '\u{202B}', '\u{2066}', '\u{FE00}', '\u{E0100}'] {
+            assert!(!is_default_ignorable_bn(c), "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn test_bidi_mirroring_glyph() {
+        assert_eq!(bidi_mirroring_glyph('('), Some(')'));
+        assert_eq!(bidi_mirroring_glyph(')'), Some('('));
+        assert_eq!(bidi_mirroring_glyph('«'), Some('»'));
+        assert_eq!(bidi_mirroring_glyph('\u{2264}'), Some('\u{2265}'));
+        assert_eq!(bidi_mirroring_glyph('\u{FF62}'), Some('\u{FF63}'));
+        assert_eq!(bidi_mirroring_glyph('a'), None);
+        assert_eq!(bidi_mirroring_glyph('|'), None);
+        // Mirrored, but without a mirror glyph.
+        assert_eq!(bidi_mirroring_glyph('\u{2140}'), None);
+
+        let table = mirroring::BIDI_MIRRORING_GLYPH;
+        assert!(table.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        for &(c, mirror) in table {
+            assert_eq!(bidi_mirroring_glyph(mirror), Some(c), "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn test_bidi_class_histogram() {
+        let counts = bidi_class_histogram("abc אב \u{0627}\u{0644} 123");
+        assert_eq!(counts[L.index()], 3);
+        assert_eq!(counts[R.index()], 2);
+        assert_eq!(counts[AL.index()], 2);
+        assert_eq!(counts[EN.index()], 3);
+        assert_eq!(counts[WS.index()], 3);
+        assert_eq!(counts.iter().sum::<u32>(), 13);
+
+        assert_eq!(bidi_class_histogram(""), [0; 23]);
+    }
+
+    #[test]
+    fn test_parse() {
+        for &class in BIDI_CLASSES.iter() {
+            assert_eq!(class.short_name().parse(), Ok(class));
+            assert_eq!(class.short_name(), format!("{:?}", class));
+        }
+        assert_eq!("al".parse::<BidiClass>(), Err(BidiClassParseError("al".to_owned())));
+        assert_eq!("".parse::<BidiClass>(), Err(BidiClassParseError("".to_owned())));
+
+        let s = "L R  AL\tEN";
+        let classes = parse_class_sequence(s).unwrap();
+        assert_eq!(classes, vec![L, R, AL, EN]);
+        let names: Vec<&str> = classes.iter().map(|class| class.short_name()).collect();
+        assert_eq!(names.join(" "), "L R AL EN");
+
+        assert_eq!(parse_class_sequence(""), Ok(vec![]));
+        assert_eq!(
+            parse_class_sequence("L X R"),
+            Err(BidiClassParseError("X".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_is_private_use() {
+        assert!(!is_private_use('\u{D7FF}'));
+        assert!(is_private_use('\u{E000}'));
+        assert!(is_private_use('\u{F8FF}'));
+        assert!(!is_private_use('\u{F900}'));
+        assert!(!is_private_use('\u{EFFFF}'));
+        assert!(is_private_use('\u{F0000}'));
+        assert!(is_private_use('\u{FFFFD}'));
+        assert!(!is_private_use('\u{FFFFE}'));
+        assert!(is_private_use('\u{100000}'));
+        assert!(is_private_use('\u{10FFFD}'));
+        assert!(!is_private_use('\u{10FFFE}'));
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn test_bidi_classes_ascii_fast() {
+        fn scalar(text: &str) -> Vec<BidiClass> {
+            text.chars()
+                .flat_map(|c| repeat(bidi_class(c)).take(c.len_utf8()))
+                .collect()
+        }
+
+        let ascii: String = (0u8..128).map(char::from).collect();
+        let mut out = Vec::new();
+        bidi_classes_ascii_fast(ascii.as_bytes(), &mut out);
+        assert_eq!(out, scalar(&ascii));
+
+        for text in &["", "abc", "Hello, world! 12.5% (ok)", "abcdefgh אבג 123 \u{0627}", "ש"] {
+            let mut out = vec![WS];
+            bidi_classes_ascii_fast(text.as_bytes(), &mut out);
+            assert_eq!(out[0], WS);
+            assert_eq!(&out[1..], &scalar(text)[..]);
+        }
+    }
+
+    #[test]
+    fn test_prefers_upright() {
+        assert!(bidi_class('漢').prefers_upright());
+        assert!(bidi_class('か').prefers_upright());
+        assert!(EN.prefers_upright());
+        assert!(AN.prefers_upright());
+        assert!(!R.prefers_upright());
+        assert!(!AL.prefers_upright());
+        assert!(!WS.prefers_upright());
+        assert!(!ON.prefers_upright());
+        assert!(!LRI.prefers_upright());
+    }
+
+    #[test]
+    fn test_weak_class_predicates() {
+        for &class in BIDI_CLASSES.iter() {
+            let name = class.short_name();
+            assert_eq!(class.is_european_number_terminator(), name == "ET", "{}", name);
+            assert_eq!(class.is_separator(), name == "ES" || name == "CS", "{}", name);
+            assert_eq!(class.is_number(), name == "EN" || name == "AN", "{}", name);
+        }
+        assert!(bidi_class('%').is_european_number_terminator());
+        assert!(bidi_class(',').is_separator());
+        assert!(!bidi_class('\t').is_separator());
+        assert!(bidi_class('\u{0661}').is_number());
+    }
+
     #[test]
     fn test_ascii() {
         assert_eq!(bidi_class('\u{0000}'), BN);
@@ -0,0 +1,139 @@
+// Copyright 2017 The Servo Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Errors of the fallible `BidiInfo` constructors
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use super::Level;
+
+/// Errors that can occur when the input of a `BidiInfo` constructor, or a `BidiInfo` itself, is
+/// inconsistent
+#[derive(Clone, Debug, PartialEq)]
+pub enum BidiError {
+    /// A slice given along with the text doesn't have the expected length, e.g. there isn't
+    /// exactly one paragraph level per paragraph.
+    MismatchedLength {
+        /// The length implied by the text.
+        expected: usize,
+        /// The length of the given slice.
+        actual: usize,
+    },
+    /// A level is deeper than the algorithm allows: `max_explicit_depth()` for a paragraph level,
+    /// or `max_implicit_depth()` for a resolved level.
+    LevelTooDeep {
+        /// The offending level.
+        level: Level,
+    },
+    /// The paragraph at this index of `paragraphs` doesn't start where the previous one ended
+    /// (or at the start of the text), or the last one doesn't end at the end of the text.
+    InvalidParagraphRange {
+        /// The index of the paragraph.
+        index: usize,
+    },
+    /// The class of the directional formatting character at this byte index doesn't match the
+    /// character.
+    InconsistentClass {
+        /// The byte index of the character.
+        index: usize,
+    },
+    /// A byte index that must be at a character boundary of the text isn't.
+    NonCharBoundary {
+        /// The byte index.
+        index: usize,
+    },
+    /// A range of the text whose class is overridden is reversed or extends past the end of the
+    /// text.
+    InvalidOverrideRange {
+        /// The range.
+        range: Range<usize>,
+    },
+    /// The byte index doesn't start a U+FFFC OBJECT REPLACEMENT CHARACTER in the text.
+    NotObjectReplacementChar {
+        /// The byte index.
+        index: usize,
+    },
+    /// The embedding or isolate initiator at this byte index would nest deeper than
+    /// `max_explicit_depth()`.
+    IsolateOverflow {
+        /// The byte index of the initiator.
+        index: usize,
+    },
+}
+
+impl fmt::Display for BidiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BidiError::MismatchedLength { expected, actual } => {
+                write!(f, "expected a length of {}, got {}", expected, actual)
+            }
+            BidiError::LevelTooDeep { level } => {
+                write!(f, "level {} is too deep", level.number())
+            }
+            BidiError::InvalidParagraphRange { index } => {
+                write!(f, "paragraph {} doesn't continue the previous one", index)
+            }
+            BidiError::InconsistentClass { index } => {
+                write!(f, "the class at byte {} doesn't match its formatting character", index)
+            }
+            BidiError::NonCharBoundary { index } => {
+                write!(f, "byte {} is not at a character boundary", index)
+            }
+            BidiError::InvalidOverrideRange { ref range } => {
+                write!(f, "invalid override range {:?}", range)
+            }
+            BidiError::NotObjectReplacementChar { index } => {
+                write!(f, "byte {} is not the start of an object replacement character", index)
+            }
+            BidiError::IsolateOverflow { index } => {
+                write!(f, "the initiator at byte {} overflows the directional status stack", index)
+            }
+        }
+    }
+}
+
+impl Error for BidiError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            BidiError::MismatchedLength { expected: 2, actual: 1 }.to_string(),
+            "expected a length of 2, got 1"
+        );
+        assert_eq!(
+            BidiError::LevelTooDeep { level: Level::new(126).unwrap() }.to_string(),
+            "level 126 is too deep"
+        );
+        assert_eq!(
+            BidiError::NonCharBoundary { index: 1 }.to_string(),
+            "byte 1 is not at a character boundary"
+        );
+        assert_eq!(
+            BidiError::InvalidOverrideRange { range: Range { start: 3, end: 2 } }.to_string(),
+            "invalid override range 3..2"
+        );
+        assert_eq!(
+            BidiError::NotObjectReplacementChar { index: 2 }.to_string(),
+            "byte 2 is not the start of an object replacement character"
+        );
+        assert_eq!(
+            BidiError::IsolateOverflow { index: 6 }.to_string(),
+            "the initiator at byte 6 overflows the directional status stack"
+        );
+
+        let error: Box<dyn Error> = Box::new(BidiError::InvalidParagraphRange { index: 1 });
+        assert_eq!(error.to_string(), "paragraph 1 doesn't continue the previous one");
+    }
+}
@@ -11,15 +11,77 @@
 //!
 //! <http://www.unicode.org/reports/tr9/#Explicit_Levels_and_Directions>
 
-use super::{BidiClass, char_data::is_rtl};
+use super::{BidiClass, BidiClassSet, char_data::is_rtl};
 use super::level::Level;
 
+use std::cmp::max;
+
 use BidiClass::*;
 
+/// Counters of the explicit level resolution (rules X1-X8), for debugging.
+///
+/// Each counter is the maximum value that the counter of the same name reached while processing
+/// the text.  Non-zero overflow counts mean that the text nests embeddings or isolates deeper than
+/// `max_depth`, so that some of its explicit formatting characters were ignored.
+///
+/// <http://www.unicode.org/reports/tr9/#X1>
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Diagnostics {
+    /// The number of isolate initiators that were encountered while the stack was full, and whose
+    /// matching PDI hasn't been seen yet.
+    pub overflow_isolate_count: u32,
+
+    /// The number of embedding initiators that were encountered while the stack was full (but not
+    /// within an overflow isolate), and whose matching PDF hasn't been seen yet.
+    pub overflow_embedding_count: u32,
+
+    /// The number of isolate initiators that were pushed on the stack, and whose matching PDI
+    /// hasn't been seen yet.
+    pub valid_isolate_count: u32,
+
+    /// The byte index of the first embedding or isolate initiator that was ignored because the
+    /// stack was full, if any.
+    pub first_overflow_index: Option<usize>,
+}
+
+/// A change of the directional status stack made by an explicit formatting character.
+///
+/// See [`BidiInfo::new_with_stack_trace`](struct.BidiInfo.html#method.new_with_stack_trace).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StackEvent {
+    /// The byte index of the formatting character in the text.
+    pub index: usize,
+
+    /// Whether an entry was pushed on the stack or popped from it.
+    pub kind: StackEventKind,
+
+    /// The embedding level of the pushed or popped entry.
+    pub level: Level,
+
+    /// The override status of the pushed or popped entry.
+    pub status: OverrideStatus,
+}
+
+/// The kind of a `StackEvent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackEventKind {
+    /// An embedding, override or isolate initiator pushed a new entry (rules X2-X5c).
+    Push,
+    /// A PDF or PDI popped an entry (rules X6a and X7).
+    Pop,
+}
+
 /// Compute explicit embedding levels for one paragraph of text (X1-X8).
 ///
 /// `processing_classes[i]` must contain the `BidiClass` of the char at byte index `i`,
 /// for each char in `text`.
+///
+/// The maximum values reached by the counters are recorded in `diagnostics`, unless they already
+/// hold higher values (e.g. from a previous paragraph), as well as the byte index into `text` of
+/// the first overflow, unless one was already recorded.
+///
+/// If `events` is given, every push and pop of the directional status stack is appended to it,
+/// with byte indices into `text`.
 #[cfg_attr(feature = "flame_it", flame)]
 pub fn compute(
     text: &str,
@@ -27,6 +89,8 @@ pub fn compute(
     original_classes: &[BidiClass],
     levels: &mut [Level],
     processing_classes: &mut [BidiClass],
+    diagnostics: &mut Diagnostics,
+    mut events: Option<&mut Vec<StackEvent>>,
 ) {
     assert_eq!(text.len(), original_classes.len());
 
@@ -46,7 +110,7 @@ pub fn compute(
                 let last_level = stack.last().level;
 
                 // X5a-X5c: Isolate initiators get the level of the last entry on the stack.
-                let is_isolate = matches!(original_classes[i], RLI | LRI | FSI);
+                let is_isolate = BidiClassSet::ISOLATE_INITIATORS.contains(original_classes[i]);
                 if is_isolate {
                     levels[i] = last_level;
                     match stack.last().status {
@@ -65,15 +129,21 @@ pub fn compute(
                     overflow_embedding_count == 0
                 {
                     let new_level = new_level.unwrap();
-                    stack.push(
-                        new_level,
-                        match original_classes[i] {
-                            RLO => OverrideStatus::RTL,
-                            LRO => OverrideStatus::LTR,
-                            RLI | LRI | FSI => OverrideStatus::Isolate,
-                            _ => OverrideStatus::Neutral,
-                        },
-                    );
+                    let status = match original_classes[i] {
+                        RLO => OverrideStatus::RTL,
+                        LRO => OverrideStatus::LTR,
+                        RLI | LRI | FSI => OverrideStatus::Isolate,
+                        _ => OverrideStatus::Neutral,
+                    };
+                    stack.push(new_level, status);
+                    if let Some(ref mut events) = events {
+                        events.push(StackEvent {
+                            index: i,
+                            kind: StackEventKind::Push,
+                            level: new_level,
+                            status,
+                        });
+                    }
                     if is_isolate {
                         valid_isolate_count += 1;
                     } else {
@@ -81,10 +151,13 @@ pub fn compute(
                         // See the reference implementations for comparison.
                         levels[i] = new_level;
                     }
-                } else if is_isolate {
-                    overflow_isolate_count += 1;
-                } else if overflow_isolate_count == 0 {
-                    overflow_embedding_count += 1;
+                } else {
+                    diagnostics.first_overflow_index.get_or_insert(i);
+                    if is_isolate {
+                        overflow_isolate_count += 1;
+                    } else if overflow_isolate_count == 0 {
+                        overflow_embedding_count += 1;
+                    }
                 }
             }
 
@@ -94,12 +167,18 @@ pub fn compute(
                     overflow_isolate_count -= 1;
                 } else if valid_isolate_count > 0 {
                     overflow_embedding_count = 0;
-                    loop {
-                        // Pop everything up to and including the last Isolate status.
-                        match stack.vec.pop() {
-                            None |
-                            Some(Status { status: OverrideStatus::Isolate, .. }) => break,
-                            _ => continue,
+                    // Pop everything up to and including the last Isolate status.
+                    while let Some(Status { level, status }) = stack.vec.pop() {
+                        if let Some(ref mut events) = events {
+                            events.push(StackEvent {
+                                index: i,
+                                kind: StackEventKind::Pop,
+                                level,
+                                status,
+                            });
+                        }
+                        if status == OverrideStatus::Isolate {
+                            break;
                         }
                     }
                     valid_isolate_count -= 1;
@@ -123,7 +202,15 @@ pub fn compute(
                     continue;
                 }
                 if stack.last().status != OverrideStatus::Isolate && stack.vec.len() >= 2 {
-                    stack.vec.pop();
+                    let Status { level, status } = stack.vec.pop().unwrap();
+                    if let Some(ref mut events) = events {
+                        events.push(StackEvent {
+                            index: i,
+                            kind: StackEventKind::Pop,
+                            level,
+                            status,
+                        });
+                    }
                 }
                 // The spec doesn't explicitly mention this step, but it is necessary.
                 // See the reference implementations for comparison.
@@ -145,6 +232,13 @@ pub fn compute(
             }
         }
 
+        diagnostics.overflow_isolate_count =
+            max(diagnostics.overflow_isolate_count, overflow_isolate_count);
+        diagnostics.overflow_embedding_count =
+            max(diagnostics.overflow_embedding_count, overflow_embedding_count);
+        diagnostics.valid_isolate_count =
+            max(diagnostics.valid_isolate_count, valid_isolate_count);
+
         // Handle multi-byte characters.
         for j in 1..c.len_utf8() {
             levels[i + j] = levels[i];
@@ -159,11 +253,18 @@ struct Status {
     status: OverrideStatus,
 }
 
-#[derive(PartialEq)]
-enum OverrideStatus {
+/// The directional override status of an entry in the directional status stack.
+///
+/// <http://www.unicode.org/reports/tr9/#X1>
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverrideStatus {
+    /// No override, as for embeddings.
     Neutral,
+    /// Right-to-left override (RLO).
     RTL,
+    /// Left-to-right override (LRO).
     LTR,
+    /// An isolate (LRI, RLI or FSI).
     Isolate,
 }
 
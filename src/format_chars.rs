@@ -40,3 +40,60 @@ pub const PDF: char = '\u{202C}';
 pub const LRO: char = '\u{202D}';
 /// RIGHT-TO-LEFT OVERRIDE
 pub const RLO: char = '\u{202E}';
+
+/// The directional formatting characters above, sorted by code point.
+const SORTED_FORMAT_CHARS: [char; 12] =
+    [ALM, LRM, RLM, LRE, RLE, PDF, LRO, RLO, LRI, RLI, FSI, PDI];
+
+/// If `c` is one of the directional formatting characters above: an implicit mark (ALM, LRM,
+/// RLM), an isolate (LRI, RLI, FSI, PDI), or an embedding or override (LRE, RLE, PDF, LRO, RLO).
+#[inline]
+pub fn is_explicit_format_char(c: char) -> bool {
+    // Most characters are outside of this range, so that they are rejected by two comparisons.
+    (ALM..=PDI).contains(&c) && SORTED_FORMAT_CHARS.binary_search(&c).is_ok()
+}
+
+/// Find the directional formatting characters in `text`, along with their byte offsets.
+///
+/// This is useful to inspect or sanitize the bidi controls of untrusted text.  See
+/// [`is_explicit_format_char()`](fn.is_explicit_format_char.html) for the characters that are
+/// included.
+pub fn find_format_chars(text: &str) -> Vec<(usize, char)> {
+    text.char_indices()
+        .filter(|&(_, c)| is_explicit_format_char(c))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_explicit_format_char() {
+        for &c in &[ALM, LRM, RLM, LRI, RLI, FSI, PDI, LRE, RLE, PDF, LRO, RLO] {
+            assert!(is_explicit_format_char(c));
+        }
+        for &c in &['a', ' ', '\u{061B}', '\u{200D}', '\u{2029}', '\u{202F}', '\u{206A}'] {
+            assert!(!is_explicit_format_char(c));
+        }
+
+        assert!(SORTED_FORMAT_CHARS.windows(2).all(|pair| pair[0] < pair[1]));
+        for c in (0..0x3_0000).filter_map(::std::char::from_u32) {
+            assert_eq!(
+                is_explicit_format_char(c),
+                matches!(c, ALM | LRM | RLM | LRI | RLI | FSI | PDI | LRE | RLE | PDF | LRO | RLO),
+                "{:?}",
+                c
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_format_chars() {
+        assert_eq!(find_format_chars("abc"), vec![]);
+        assert_eq!(
+            find_format_chars("a\u{202E}bc\u{202C} \u{2067}א\u{200F}\u{2069}"),
+            vec![(1, RLO), (6, PDF), (10, RLI), (15, RLM), (18, PDI)]
+        );
+    }
+}
@@ -11,17 +11,131 @@
 
 use std::cmp::max;
 
-use super::BidiClass;
+use super::{BidiClass, BidiClassSet};
 use super::prepare::{IsolatingRunSequence, LevelRun, not_removed_by_x9, removed_by_x9};
 use super::level::Level;
 
 use BidiClass::*;
 
+/// A change of the class of a character by one of the weak or neutral rules (W1-W7, N1-N2)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RuleEvent {
+    /// The name of the rule, e.g. `"W2"`.
+    pub rule: &'static str,
+    /// The byte index of the character.
+    pub index: usize,
+    /// The class of the character before the rule was applied.
+    pub from: BidiClass,
+    /// The class of the character after the rule was applied.
+    pub to: BidiClass,
+}
+
+/// Optional record of the `RuleEvent`s of the implicit resolution
+#[derive(Debug)]
+pub struct Trace {
+    events: Option<Vec<RuleEvent>>,
+    // Byte index of the current paragraph within the text.
+    offset: usize,
+}
+
+impl Trace {
+    /// A trace that records nothing.
+    pub fn disabled() -> Trace {
+        Trace {
+            events: None,
+            offset: 0,
+        }
+    }
+
+    /// A trace that records every change of class.
+    #[cfg(feature = "trace")]
+    pub fn enabled() -> Trace {
+        Trace {
+            events: Some(Vec::new()),
+            offset: 0,
+        }
+    }
+
+    /// Record the indices of the following events relative to `offset` instead of the start of
+    /// the current paragraph.
+    pub fn set_paragraph_start(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    /// The recorded events, in the order they happened.
+    #[cfg(feature = "trace")]
+    pub fn into_events(self) -> Vec<RuleEvent> {
+        self.events.unwrap_or_default()
+    }
+
+    /// Set `classes[index]` to `class` as specified by `rule`, and record the change if enabled.
+    #[inline]
+    fn set(
+        &mut self,
+        classes: &mut [BidiClass],
+        index: usize,
+        class: BidiClass,
+        rule: &'static str,
+    ) {
+        if let Some(ref mut events) = self.events {
+            if classes[index] != class {
+                events.push(RuleEvent {
+                    rule,
+                    index: self.offset + index,
+                    from: classes[index],
+                    to: class,
+                });
+            }
+        }
+        classes[index] = class;
+    }
+}
+
 /// 3.3.4 Resolving Weak Types
 ///
 /// <http://www.unicode.org/reports/tr9/#Resolving_Weak_Types>
 #[cfg_attr(feature = "flame_it", flame)]
-pub fn resolve_weak(sequence: &IsolatingRunSequence, processing_classes: &mut [BidiClass]) {
+pub fn resolve_weak(
+    sequence: &IsolatingRunSequence,
+    processing_classes: &mut [BidiClass],
+    trace: &mut Trace,
+) {
+    // Fast path: rules W1-W7 leave strong (other than AL) and neutral classes unchanged, so there's
+    // nothing to do for a sequence of only those.
+    if !needs_weak_rules(sequence, processing_classes) {
+        return;
+    }
+    resolve_weak_impl(sequence, processing_classes, trace, true);
+}
+
+/// The classes that rules W1-W7 may change, or that affect how they change other classes: the
+/// weak classes other than `BN` (which is skipped, per rule X9), and `AL` (rules W2 and W3).
+const WEAK_RULE_INPUTS: BidiClassSet = BidiClassSet::empty()
+    .with(EN)
+    .with(ES)
+    .with(ET)
+    .with(AN)
+    .with(CS)
+    .with(NSM)
+    .with(AL);
+
+/// If any class of `sequence` is one of `WEAK_RULE_INPUTS`, i.e. rules W1-W7 may change it.
+fn needs_weak_rules(sequence: &IsolatingRunSequence, processing_classes: &[BidiClass]) -> bool {
+    sequence
+        .runs
+        .iter()
+        .flat_map(Clone::clone)
+        .any(|i| WEAK_RULE_INPUTS.contains(processing_classes[i]))
+}
+
+/// Apply rules W1-W7, skipping over runs of characters that the rules leave unchanged if
+/// `skip_runs` is set.
+fn resolve_weak_impl(
+    sequence: &IsolatingRunSequence,
+    processing_classes: &mut [BidiClass],
+    trace: &mut Trace,
+    skip_runs: bool,
+) {
     // FIXME (#8): This function applies steps W1-W6 in a single pass.  This can produce
     // incorrect results in cases where a "later" rule changes the value of `prev_class` seen
     // by an "earlier" rule.  We should either split this into separate passes, or preserve
@@ -46,25 +160,26 @@ pub fn resolve_weak(sequence: &IsolatingRunSequence, processing_classes: &mut [B
         match processing_classes[i] {
             // <http://www.unicode.org/reports/tr9/#W1>
             NSM => {
-                processing_classes[i] = match prev_class {
+                let class = match prev_class {
                     RLI | LRI | FSI | PDI => ON,
                     _ => prev_class,
                 };
+                trace.set(processing_classes, i, class, "W1");
             }
             EN => {
                 if last_strong_is_al {
                     // W2. If previous strong char was AL, change EN to AN.
-                    processing_classes[i] = AN;
+                    trace.set(processing_classes, i, AN, "W2");
                 } else {
                     // W5. If a run of ETs is adjacent to an EN, change the ETs to EN.
                     for j in &et_run_indices {
-                        processing_classes[*j] = EN;
+                        trace.set(processing_classes, *j, EN, "W5");
                     }
                     et_run_indices.clear();
                 }
             }
-            // <http://www.unicode.org/reports/tr9/#W3>
-            AL => processing_classes[i] = R,
+            // W3 is applied after this pass, so that AL is still seen by W1 and W2.
+            AL => {}
 
             // <http://www.unicode.org/reports/tr9/#W4>
             ES | CS => {
@@ -73,16 +188,16 @@ pub fn resolve_weak(sequence: &IsolatingRunSequence, processing_classes: &mut [B
                     .map(|j| processing_classes[j])
                     .find(not_removed_by_x9)
                     .unwrap_or(sequence.eos);
-                processing_classes[i] = match (prev_class, processing_classes[i], next_class) {
-                    (EN, ES, EN) | (EN, CS, EN) => EN,
-                    (AN, CS, AN) => AN,
-                    (_, _, _) => ON,
+                match (prev_class, processing_classes[i], next_class) {
+                    (EN, ES, EN) | (EN, CS, EN) => trace.set(processing_classes, i, EN, "W4"),
+                    (AN, CS, AN) => trace.set(processing_classes, i, AN, "W4"),
+                    (_, _, _) => trace.set(processing_classes, i, ON, "W6"),
                 }
             }
             // <http://www.unicode.org/reports/tr9/#W5>
             ET => {
                 match prev_class {
-                    EN => processing_classes[i] = EN,
+                    EN => trace.set(processing_classes, i, EN, "W5"),
                     _ => et_run_indices.push(i), // In case this is followed by an EN.
                 }
             }
@@ -103,12 +218,21 @@ pub fn resolve_weak(sequence: &IsolatingRunSequence, processing_classes: &mut [B
             }
             _ => {}
         }
-        if prev_class != ET {
+        if !prev_class.is_european_number_terminator() {
             // W6. If we didn't find an adjacent EN, turn any ETs into ON instead.
             for j in &et_run_indices {
-                processing_classes[*j] = ON;
+                trace.set(processing_classes, *j, ON, "W6");
             }
             et_run_indices.clear();
+
+            // Fast path: a following character of the same class as the resolved `prev_class` is
+            // left unchanged by W1-W6, and leaves the state unchanged, unless it's an EN after AL
+            // (W2).  ET is excluded above since its resolution depends on what follows.
+            if skip_runs && !(prev_class == EN && last_strong_is_al) {
+                while indices.clone().next().map(|j| processing_classes[j]) == Some(prev_class) {
+                    indices.next();
+                }
+            }
         }
     }
 
@@ -118,12 +242,17 @@ pub fn resolve_weak(sequence: &IsolatingRunSequence, processing_classes: &mut [B
         for i in run.clone() {
             match processing_classes[i] {
                 EN if last_strong_is_l => {
-                    processing_classes[i] = L;
+                    trace.set(processing_classes, i, L, "W7");
                 }
                 L => {
                     last_strong_is_l = true;
                 }
-                R | AL => {
+                R => {
+                    last_strong_is_l = false;
+                }
+                // <http://www.unicode.org/reports/tr9/#W3>
+                AL => {
+                    trace.set(processing_classes, i, R, "W3");
                     last_strong_is_l = false;
                 }
                 _ => {}
@@ -134,14 +263,20 @@ pub fn resolve_weak(sequence: &IsolatingRunSequence, processing_classes: &mut [B
 
 /// 3.3.5 Resolving Neutral Types
 ///
+/// If `neutral_fallback` is given, rule N2 resolves neutrals to it instead of the embedding
+/// direction.
+///
 /// <http://www.unicode.org/reports/tr9/#Resolving_Neutral_Types>
 #[cfg_attr(feature = "flame_it", flame)]
 pub fn resolve_neutral(
     sequence: &IsolatingRunSequence,
     levels: &[Level],
     processing_classes: &mut [BidiClass],
+    neutral_fallback: Option<BidiClass>,
+    trace: &mut Trace,
 ) {
-    let e: BidiClass = levels[sequence.runs[0].start].bidi_class();
+    let e: BidiClass =
+        neutral_fallback.unwrap_or_else(|| levels[sequence.runs[0].start].bidi_class());
     let mut indices = sequence.runs.iter().flat_map(Clone::clone);
     let mut prev_class = sequence.sos;
 
@@ -180,18 +315,22 @@ pub fn resolve_neutral(
             //
             // <http://www.unicode.org/reports/tr9/#N1>
             // <http://www.unicode.org/reports/tr9/#N2>
-            let new_class = match (prev_class, next_class) {
-                (L, L) => L,
+            let (new_class, rule) = match (prev_class, next_class) {
+                (L, L) => (L, "N1"),
                 (R, R) | (R, AN) | (R, EN) | (AN, R) | (AN, AN) | (AN, EN) | (EN, R) |
-                (EN, AN) | (EN, EN) => R,
-                (_, _) => e,
+                (EN, AN) | (EN, EN) => (R, "N1"),
+                (_, _) => (e, "N2"),
             };
             for j in &ni_run {
-                processing_classes[*j] = new_class;
+                trace.set(processing_classes, *j, new_class, rule);
             }
             ni_run.clear();
         }
-        prev_class = processing_classes[i];
+        // Characters removed by X9 are skipped, so they must not become the `prev_class` seen by
+        // the next run of neutrals.
+        if not_removed_by_x9(&processing_classes[i]) {
+            prev_class = processing_classes[i];
+        }
     }
 }
 
@@ -224,5 +363,110 @@ pub fn resolve_levels(original_classes: &[BidiClass], levels: &mut [Level]) -> L
 /// <http://www.unicode.org/reports/tr9/#NI>
 #[allow(non_snake_case)]
 fn is_NI(class: BidiClass) -> bool {
-    matches!(class, B | S | WS | ON | FSI | LRI | RLI | PDI)
+    BidiClassSet::NEUTRAL_OR_ISOLATE.contains(class)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An isolating run sequence made of a single level run of `len` bytes, with `sos` at both
+    /// ends.
+    fn run_sequence(sos: BidiClass, len: usize) -> IsolatingRunSequence {
+        let run: LevelRun = 0..len;
+        IsolatingRunSequence {
+            runs: vec![run],
+            sos,
+            eos: sos,
+        }
+    }
+
+    /// Apply rules W1-W7 to a single isolating run sequence holding `classes`.
+    fn resolve_weak_classes(sos: BidiClass, classes: &[BidiClass]) -> Vec<BidiClass> {
+        let sequence = run_sequence(sos, classes.len());
+        let mut classes = classes.to_vec();
+        resolve_weak(&sequence, &mut classes, &mut Trace::disabled());
+        classes
+    }
+
+    #[test]
+    fn test_skip_runs() {
+        let classes = [
+            L, L, L, R, R, AL, AL, EN, EN, ES, EN, EN, ET, ET, EN, CS, CS, AN, AN, CS, AN, NSM,
+            NSM, ON, ON, WS, BN, WS, L, EN, EN, ET, ET, ON, AL, ET, EN, EN, NSM, R, EN, ES, ES,
+        ];
+        // Compare with the scalar path on every window of the classes, and every sos.
+        for start in 0..classes.len() {
+            for end in start..classes.len() + 1 {
+                for &sos in &[L, R] {
+                    let sequence = run_sequence(sos, end - start);
+                    let mut fast = classes[start..end].to_vec();
+                    let mut scalar = fast.clone();
+                    resolve_weak_impl(&sequence, &mut fast, &mut Trace::disabled(), true);
+                    resolve_weak_impl(&sequence, &mut scalar, &mut Trace::disabled(), false);
+                    assert_eq!(fast, scalar, "{:?} {:?}", sos, &classes[start..end]);
+                }
+            }
+        }
+
+        // A long run of a single class
+        let classes = vec![EN; 1_000_000];
+        assert_eq!(resolve_weak_classes(L, &classes), vec![L; 1_000_000]);
+        let classes = vec![R; 1_000_000];
+        assert_eq!(resolve_weak_classes(L, &classes), classes);
+    }
+
+    #[test]
+    fn test_needs_weak_rules() {
+        // Strong and neutral classes only: the fast path returns the same as the full path.
+        let classes = [L, WS, R, ON, BN, LRI, L, PDI, S, R, B];
+        let sequence = run_sequence(L, classes.len());
+        assert!(!needs_weak_rules(&sequence, &classes));
+        let mut fast = classes.to_vec();
+        let mut full = classes.to_vec();
+        resolve_weak(&sequence, &mut fast, &mut Trace::disabled());
+        resolve_weak_impl(&sequence, &mut full, &mut Trace::disabled(), false);
+        assert_eq!(fast, full);
+        assert_eq!(fast, classes);
+
+        // Any class that the weak rules may change takes the full path.
+        for &class in &[EN, ES, ET, AN, CS, NSM, AL] {
+            let classes = [R, WS, class, L];
+            assert!(needs_weak_rules(&run_sequence(L, classes.len()), &classes), "{:?}", class);
+        }
+        let classes = [AL, ET, EN, WS, L, CS, NSM];
+        let sequence = run_sequence(L, classes.len());
+        let mut fast = classes.to_vec();
+        let mut full = classes.to_vec();
+        resolve_weak(&sequence, &mut fast, &mut Trace::disabled());
+        resolve_weak_impl(&sequence, &mut full, &mut Trace::disabled(), false);
+        assert_eq!(fast, full);
+        assert_eq!(fast, vec![R, ON, AN, WS, L, ON, ON]);
+    }
+
+    // <http://www.unicode.org/reports/tr9/#W1>
+    #[test]
+    fn test_nsm() {
+        // After a strong character
+        assert_eq!(resolve_weak_classes(L, &[R, NSM]), vec![R, R]);
+        assert_eq!(resolve_weak_classes(R, &[L, NSM, NSM]), vec![L, L, L]);
+
+        // After a number
+        assert_eq!(resolve_weak_classes(R, &[EN, NSM]), vec![EN, EN]);
+        assert_eq!(resolve_weak_classes(R, &[AN, NSM]), vec![AN, AN]);
+
+        // At the start of the sequence, NSM takes the class of sos.
+        assert_eq!(resolve_weak_classes(L, &[NSM, R]), vec![L, R]);
+        assert_eq!(resolve_weak_classes(R, &[NSM, L]), vec![R, L]);
+
+        // After an isolate initiator or PDI
+        assert_eq!(resolve_weak_classes(L, &[PDI, NSM]), vec![PDI, ON]);
+
+        // An NSM after AL becomes AL, so the EN after it becomes AN (W2), then both ALs become R
+        // (W3).
+        assert_eq!(resolve_weak_classes(L, &[AL, NSM, EN]), vec![R, R, AN]);
+
+        // Characters removed by X9 are skipped.
+        assert_eq!(resolve_weak_classes(L, &[R, BN, NSM]), vec![R, BN, R]);
+    }
 }
@@ -0,0 +1,147 @@
+// Copyright 2017 The Servo Project Developers. See the
+// COPYRIGHT file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Analysis of text that arrives in chunks
+
+use super::{bidi_class, BidiClass, BidiInfo, BidiInfoBuf, Level, ParagraphInfo};
+
+/// Bidi analysis of a text that is received in chunks, e.g. from a streaming parser.
+///
+/// Paragraphs are independent of each other (rule P1), so each paragraph is analyzed as soon as
+/// its paragraph separator has been fed, even if it spans several chunks.  The text after the last
+/// separator is buffered until more chunks arrive, or until `finish()` is called.  The result is
+/// the same as analyzing the whole text at once with `BidiInfo::new()`.
+///
+/// ```rust
+/// use unicode_bidi::{BidiInfo, IncrementalBidi};
+///
+/// let mut incremental = IncrementalBidi::new(None);
+/// incremental.feed("abc\nא");
+/// incremental.feed("בג\n");
+/// assert_eq!(incremental.paragraphs().len(), 2);
+/// let buf = incremental.finish();
+/// assert_eq!(buf.bidi_info(), BidiInfo::new("abc\nאבג\n", None));
+/// ```
+#[derive(Clone, Debug)]
+pub struct IncrementalBidi {
+    /// The analysis of the completed paragraphs.
+    done: BidiInfoBuf,
+
+    /// The text fed after the last paragraph separator.
+    pending: String,
+}
+
+impl IncrementalBidi {
+    /// An empty analysis, which gives each paragraph the level `default_para_level` like
+    /// `BidiInfo::new()`.
+    pub fn new(default_para_level: Option<Level>) -> IncrementalBidi {
+        IncrementalBidi {
+            done: BidiInfoBuf {
+                text: String::new(),
+                original_classes: Vec::new(),
+                levels: Vec::new(),
+                paragraphs: Vec::new(),
+                default_para_level,
+            },
+            pending: String::new(),
+        }
+    }
+
+    /// Append a chunk of text, and analyze the paragraphs that it completes.
+    pub fn feed(&mut self, chunk: &str) {
+        let separator = chunk
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| bidi_class(c) == BidiClass::B);
+        match separator {
+            Some((i, c)) => {
+                let end = i + c.len_utf8();
+                self.pending.push_str(&chunk[..end]);
+                self.analyze_pending();
+                self.pending.push_str(&chunk[end..]);
+            }
+            None => self.pending.push_str(chunk),
+        }
+    }
+
+    /// The paragraphs analyzed so far, i.e. all but the one that is still being fed.  Their
+    /// ranges are byte offsets into the whole text fed so far.
+    pub fn paragraphs(&self) -> &[ParagraphInfo] {
+        &self.done.paragraphs
+    }
+
+    /// Analyze the text that was fed after the last paragraph separator, if any, and return the
+    /// analysis of the whole text.
+    pub fn finish(mut self) -> BidiInfoBuf {
+        self.analyze_pending();
+        self.done
+    }
+
+    /// Analyze the buffered text, which must end at a paragraph boundary, and append the result
+    /// to `self.done`.
+    fn analyze_pending(&mut self) {
+        let offset = self.done.text.len();
+        {
+            let BidiInfo {
+                original_classes,
+                levels,
+                paragraphs,
+                ..
+            } = BidiInfo::new(&self.pending, self.done.default_para_level);
+            self.done.original_classes.extend(original_classes);
+            self.done.levels.extend(levels);
+            self.done.paragraphs.extend(paragraphs.into_iter().map(|para| {
+                ParagraphInfo {
+                    range: offset + para.range.start..offset + para.range.end,
+                    level: para.level,
+                }
+            }));
+        }
+        self.done.text.push_str(&self.pending);
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::RTL_LEVEL;
+
+    #[test]
+    fn test_incremental_bidi() {
+        // One paragraph split across three chunks.
+        let chunks = ["abc אב", "ג 12", "3 def"];
+        let text: String = chunks.concat();
+        let mut incremental = IncrementalBidi::new(None);
+        for chunk in &chunks {
+            incremental.feed(chunk);
+            assert_eq!(incremental.paragraphs(), &[]);
+        }
+        assert_eq!(incremental.finish().bidi_info(), BidiInfo::new(&text, None));
+
+        // Paragraphs are analyzed as soon as they are complete.
+        let chunks = ["אב", "ג\nabc", "", "\u{2029}de", "f\n"];
+        let text: String = chunks.concat();
+        for &level in &[None, Some(RTL_LEVEL)] {
+            let mut incremental = IncrementalBidi::new(level);
+            let mut counts = vec![];
+            for chunk in &chunks {
+                incremental.feed(chunk);
+                counts.push(incremental.paragraphs().len());
+            }
+            assert_eq!(counts, vec![0, 1, 1, 2, 3]);
+            let buf = incremental.finish();
+            assert_eq!(buf.text, text);
+            assert_eq!(buf.bidi_info(), BidiInfo::new(&text, level));
+        }
+
+        let buf = IncrementalBidi::new(None).finish();
+        assert_eq!(buf.bidi_info(), BidiInfo::new("", None));
+    }
+}
@@ -41,7 +41,7 @@ pub const MAX_EXPLICIT_DEPTH: u8 = MAX_DEPTH;
 pub const MAX_IMPLICIT_DEPTH: u8 = MAX_DEPTH + 1;
 
 /// Errors that can occur on Level creation or mutation
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Error {
     /// Out-of-range (invalid) embedding level number.
     OutOfRangeNumber,
@@ -212,6 +212,9 @@ impl Into<u8> for Level {
 
 impl From<u8> for Level {
     /// Create level by number
+    ///
+    /// Panics if the number is larger than `max_depth + 1`.  Use
+    /// [`Level::new()`](#method.new) for a fallible conversion.
     #[inline]
     fn from(number: u8) -> Level {
         Level::new(number).expect("Level number error")
@@ -331,6 +334,17 @@ mod tests {
         assert_eq!(1u8, level.into());
     }
 
+    #[test]
+    fn test_ord() {
+        // Levels are ordered by number, regardless of direction.
+        assert!(Level(0) < Level(1));
+        assert!(Level(1) < Level(2));
+        assert!(Level(125) > Level(2));
+        let mut levels = Level::vec(&[2, 0, 125, 1]);
+        levels.sort();
+        assert_eq!(levels, Level::vec(&[0, 1, 2, 125]));
+    }
+
     #[test]
     fn test_vec() {
         assert_eq!(
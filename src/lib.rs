@@ -79,26 +79,45 @@ pub mod deprecated;
 pub mod format_chars;
 pub mod level;
 
+mod cache;
 mod char_data;
+mod error;
 mod explicit;
 mod implicit;
+mod incremental;
 mod prepare;
 
-pub use char_data::{BidiClass, bidi_class, UNICODE_VERSION};
+pub use cache::BidiCache;
+pub use char_data::{BidiClass, BidiClassParseError, BidiClassSet, BidiDataSource, bidi_class,
+                    bidi_class_histogram, bidi_mirroring_glyph, HardcodedBidiData,
+                    InvalidBidiClassIndex, is_default_ignorable_bn, parse_class_sequence,
+                    UnicodeVersion, UNICODE_VERSION};
+#[cfg(feature = "simd")]
+pub use char_data::bidi_classes_ascii_fast;
 pub use level::{Level, LTR_LEVEL, RTL_LEVEL};
-pub use prepare::LevelRun;
+pub use error::BidiError;
+pub use explicit::{Diagnostics, OverrideStatus, StackEvent, StackEventKind};
+pub use format_chars::find_format_chars;
+#[cfg(feature = "trace")]
+pub use implicit::RuleEvent;
+pub use incremental::IncrementalBidi;
+pub use prepare::{IsolatingRunSequence, LevelRun};
 
 use std::borrow::Cow;
-use std::cmp::{max, min};
-use std::iter::repeat;
+use std::cmp::{max, min, Ordering, Reverse};
+use std::iter::{self, repeat};
+use std::mem;
 use std::ops::Range;
+use std::str::CharIndices;
+use std::str::FromStr;
 
 use BidiClass::*;
 use format_chars as chars;
+use implicit::Trace;
 
 
 /// Bidi information about a single paragraph
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ParagraphInfo {
     /// The paragraphs boundaries within the text, as byte indices.
     ///
@@ -111,10 +130,87 @@ pub struct ParagraphInfo {
     pub level: Level,
 }
 
+/// How to determine the embedding level of each paragraph.
+///
+/// `BidiInfo::new()` takes the paragraph level as an `Option<Level>` instead, where `None`
+/// corresponds to `Auto`, `Some(LTR_LEVEL)` to `Ltr` and `Some(RTL_LEVEL)` to `Rtl`.  `Fallback`
+/// has no `Option<Level>` equivalent.
+///
+/// <http://www.unicode.org/reports/tr9/#The_Paragraph_Level>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParaLevelMode {
+    /// Apply rules P2 and P3 to each paragraph: use the direction of its first strong character,
+    /// or LTR if it has none.  This is the default.
+    Auto,
+    /// Every paragraph is LTR (level 0).
+    Ltr,
+    /// Every paragraph is RTL (level 1).
+    Rtl,
+    /// Like `Auto`, but paragraphs without any strong character get the given level instead of
+    /// LTR.
+    Fallback(Level),
+}
+
+impl Default for ParaLevelMode {
+    fn default() -> ParaLevelMode {
+        ParaLevelMode::Auto
+    }
+}
+
+impl ParaLevelMode {
+    /// The paragraph level to use regardless of the text, if any.
+    fn default_para_level(self) -> Option<Level> {
+        match self {
+            ParaLevelMode::Auto | ParaLevelMode::Fallback(_) => None,
+            ParaLevelMode::Ltr => Some(LTR_LEVEL),
+            ParaLevelMode::Rtl => Some(RTL_LEVEL),
+        }
+    }
+
+    /// The paragraph level to use if rule P2 finds no strong character.
+    fn fallback_level(self) -> Level {
+        match self {
+            ParaLevelMode::Fallback(level) => level,
+            _ => LTR_LEVEL,
+        }
+    }
+}
+
+/// Error returned when parsing a string that is not a direction hint, holding that string.
+///
+/// See [`para_level_from_hint()`](fn.para_level_from_hint.html).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParaLevelModeParseError(pub String);
+
+impl FromStr for ParaLevelMode {
+    type Err = ParaLevelModeParseError;
+
+    /// The mode for a direction hint, as in `para_level_from_hint()`.
+    fn from_str(s: &str) -> Result<ParaLevelMode, ParaLevelModeParseError> {
+        if s.eq_ignore_ascii_case("ltr") {
+            Ok(ParaLevelMode::Ltr)
+        } else if s.eq_ignore_ascii_case("rtl") {
+            Ok(ParaLevelMode::Rtl)
+        } else if s.eq_ignore_ascii_case("auto") {
+            Ok(ParaLevelMode::Auto)
+        } else {
+            Err(ParaLevelModeParseError(s.to_owned()))
+        }
+    }
+}
+
+/// The `ParaLevelMode` for a direction hint, like the values of HTML's `dir` attribute: `"ltr"`
+/// gives `Ltr`, `"rtl"` gives `Rtl` and `"auto"` gives `Auto`.
+///
+/// The hint is matched ignoring ASCII case, as in HTML.  Any other string is an error.
+pub fn para_level_from_hint(hint: &str) -> Result<ParaLevelMode, ParaLevelModeParseError> {
+    hint.parse()
+}
+
 /// Initial bidi information of the text.
 ///
 /// Contains the text paragraphs and `BidiClass` of its characters.
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct InitialInfo<'text> {
     /// The text
     pub text: &'text str,
@@ -125,6 +221,12 @@ pub struct InitialInfo<'text> {
 
     /// The boundaries and level of each paragraph within the text.
     pub paragraphs: Vec<ParagraphInfo>,
+
+    /// The paragraph level that was given instead of applying rules P2 and P3, if any.
+    ///
+    /// This field was added after the others, so code that builds an `InitialInfo` with a struct
+    /// literal has to set it, which makes this a breaking change for the next release.
+    pub default_para_level: Option<Level>,
 }
 
 impl<'text> InitialInfo<'text> {
@@ -137,23 +239,74 @@ impl<'text> InitialInfo<'text> {
     /// remain FSI, and it's up to later stages to treat these as LRI when needed.
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn new(text: &str, default_para_level: Option<Level>) -> InitialInfo {
-        let mut original_classes = Vec::with_capacity(text.len());
+        InitialInfo::new_with_classifier(text, default_para_level, LTR_LEVEL, |_, c| bidi_class(c))
+    }
+
+    /// Find the paragraphs and BidiClasses in a string of text, choosing the paragraph levels
+    /// according to `mode`.
+    ///
+    /// See [`InitialInfo::new()`](#method.new).
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_with_mode(text: &str, mode: ParaLevelMode) -> InitialInfo<'_> {
+        InitialInfo::new_with_classifier(
+            text,
+            mode.default_para_level(),
+            mode.fallback_level(),
+            |_, c| bidi_class(c),
+        )
+    }
 
-        // The stack contains the starting byte index for each nested isolate we're inside.
+    /// Like `new()`, but paragraphs without any strong character get `fallback_level` instead of
+    /// level zero, and the class of each character is given by `class_of`, from its byte index
+    /// and the character itself.
+    fn new_with_classifier<F: FnMut(usize, char) -> BidiClass>(
+        text: &str,
+        default_para_level: Option<Level>,
+        fallback_level: Level,
+        mut class_of: F,
+    ) -> InitialInfo<'_> {
+        #[cfg(feature = "flame_it")] flame::start("InitialInfo::new(): iter text.char_indices()");
+
+        let chars = text.char_indices().map(|(i, c)| (i, c.len_utf8(), class_of(i, c)));
+        let (original_classes, paragraphs) =
+            InitialInfo::split_paragraphs(chars, default_para_level, fallback_level);
+        assert_eq!(original_classes.len(), text.len());
+
+        #[cfg(feature = "flame_it")] flame::end("InitialInfo::new(): iter text.char_indices()");
+
+        InitialInfo {
+            text,
+            original_classes,
+            paragraphs,
+            default_para_level,
+        }
+    }
+
+    /// Find the paragraphs of a text, given the byte index, length and class of each of its
+    /// characters, and return the class of each byte along with the paragraphs.
+    ///
+    /// Paragraphs without any strong character get `fallback_level`, unless `default_para_level` is
+    /// given.  The class of each FSI is set to LRI or RLI if a strong character is found before the
+    /// matching PDI.
+    fn split_paragraphs<I: Iterator<Item = (usize, usize, BidiClass)>>(
+        chars: I,
+        default_para_level: Option<Level>,
+        fallback_level: Level,
+    ) -> (Vec<BidiClass>, Vec<ParagraphInfo>) {
+        let mut original_classes = Vec::with_capacity(chars.size_hint().0);
+
+        // The stack contains the starting byte index and length of each nested isolate initiator
+        // we're inside.
         let mut isolate_stack = Vec::new();
         let mut paragraphs = Vec::new();
 
         let mut para_start = 0;
         let mut para_level = default_para_level;
 
-        #[cfg(feature = "flame_it")] flame::start("InitialInfo::new(): iter text.char_indices()");
-
-        for (i, c) in text.char_indices() {
-            let class = bidi_class(c);
-
+        for (i, len, class) in chars {
             #[cfg(feature = "flame_it")] flame::start("original_classes.extend()");
 
-            original_classes.extend(repeat(class).take(c.len_utf8()));
+            original_classes.extend(repeat(class).take(len));
 
             #[cfg(feature = "flame_it")] flame::end("original_classes.extend()");
 
@@ -162,11 +315,12 @@ impl<'text> InitialInfo<'text> {
                 B => {
                     // P1. Split the text into separate paragraphs. The paragraph separator is kept
                     // with the previous paragraph.
-                    let para_end = i + c.len_utf8();
+                    let para_end = i + len;
                     paragraphs.push(ParagraphInfo {
                         range: para_start..para_end,
-                        // P3. If no character is found in p2, set the paragraph level to zero.
-                        level: para_level.unwrap_or(LTR_LEVEL),
+                        // P3. If no character is found in p2, set the paragraph level to zero
+                        // (or to the fallback level, if one was given).
+                        level: para_level.unwrap_or(fallback_level),
                     });
                     // Reset state for the start of the next paragraph.
                     para_start = para_end;
@@ -179,11 +333,11 @@ impl<'text> InitialInfo<'text> {
 
                 L | R | AL => {
                     match isolate_stack.last() {
-                        Some(&start) => {
+                        Some(&(start, start_len)) => {
                             if original_classes[start] == FSI {
                                 // X5c. If the first strong character between FSI and its matching
                                 // PDI is R or AL, treat it as RLI. Otherwise, treat it as LRI.
-                                for j in 0..chars::FSI.len_utf8() {
+                                for j in 0..start_len {
                                     original_classes[start + j] =
                                         if class == L { LRI } else { RLI };
                                 }
@@ -202,7 +356,7 @@ impl<'text> InitialInfo<'text> {
                 }
 
                 RLI | LRI | FSI => {
-                    isolate_stack.push(i);
+                    isolate_stack.push((i, len));
                 }
 
                 PDI => {
@@ -212,22 +366,54 @@ impl<'text> InitialInfo<'text> {
                 _ => {}
             }
         }
-        if para_start < text.len() {
+        if para_start < original_classes.len() {
             paragraphs.push(ParagraphInfo {
-                range: para_start..text.len(),
-                level: para_level.unwrap_or(LTR_LEVEL),
+                range: para_start..original_classes.len(),
+                level: para_level.unwrap_or(fallback_level),
             });
         }
-        assert_eq!(original_classes.len(), text.len());
-
-        #[cfg(feature = "flame_it")] flame::end("InitialInfo::new(): iter text.char_indices()");
+        (original_classes, paragraphs)
+    }
+}
 
-        InitialInfo {
-            text,
-            original_classes,
-            paragraphs,
+/// Split possibly overlapping class overrides into disjoint ranges sorted by their start, with
+/// the last override winning where they overlap.
+fn merge_overrides(overrides: &[(Range<usize>, BidiClass)]) -> Vec<(Range<usize>, BidiClass)> {
+    let mut merged: Vec<(Range<usize>, BidiClass)> = Vec::with_capacity(overrides.len());
+    for &(ref range, class) in overrides {
+        if range.start == range.end {
+            continue;
+        }
+        // Cut the part covered by this range out of the earlier ones.
+        let mut cut = Vec::with_capacity(merged.len() + 2);
+        for (earlier, earlier_class) in merged.drain(..) {
+            if earlier.end <= range.start || earlier.start >= range.end {
+                cut.push((earlier, earlier_class));
+                continue;
+            }
+            if earlier.start < range.start {
+                cut.push((earlier.start..range.start, earlier_class));
+            }
+            if earlier.end > range.end {
+                cut.push((range.end..earlier.end, earlier_class));
+            }
         }
+        cut.push((range.clone(), class));
+        merged = cut;
     }
+    merged.sort_by_key(|(range, _)| range.start);
+    merged
+}
+
+/// What `BidiInfo::resolve()` should record or tailor, besides the default algorithm.
+#[derive(Default)]
+struct ResolveOptions<'a> {
+    /// Record the changes of class made by the weak and neutral rules.
+    trace: Option<&'a mut Trace>,
+    /// Record the pushes and pops of the directional status stack.
+    stack_events: Option<&'a mut Vec<StackEvent>>,
+    /// Resolve the neutrals of rule N2 to this class instead of the embedding direction.
+    neutral_fallback: Option<BidiClass>,
 }
 
 /// Bidi information of the text.
@@ -235,8 +421,12 @@ impl<'text> InitialInfo<'text> {
 /// The `original_classes` and `levels` vectors are indexed by byte offsets into the text.  If a
 /// character is multiple bytes wide, then its class and level will appear multiple times in these
 /// vectors.
+///
+/// The accessor methods (`text()`, `original_classes()`, `levels()` and `paragraphs()`) are the
+/// preferred way to read this information.  The public fields are kept for compatibility, but
+/// their representation may change in the future.
 // TODO: Impl `struct StringProperty<T> { values: Vec<T> }` and use instead of Vec<T>
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct BidiInfo<'text> {
     /// The text
     pub text: &'text str,
@@ -249,28 +439,301 @@ pub struct BidiInfo<'text> {
 
     /// The boundaries and paragraph embedding level of each paragraph within the text.
     ///
+    /// The paragraphs cover the whole text.  If the text doesn't end with a paragraph separator,
+    /// the last paragraph ends at the end of the text.  An empty text has no paragraphs.
+    ///
     /// TODO: Use SmallVec or similar to avoid overhead when there are only one or two paragraphs?
     /// Or just don't include the first paragraph, which always starts at 0?
     pub paragraphs: Vec<ParagraphInfo>,
+
+    /// The paragraph level that was given instead of applying rules P2 and P3, if any, e.g. the
+    /// `default_para_level` of `BidiInfo::new()`.
+    ///
+    /// This is `None` for `BidiInfo::new_with_para_levels()`, which gives a level per paragraph.
+    ///
+    /// Struct literals building a `BidiInfo` must now set this field too (a breaking change), and
+    /// two analyses with the same levels are only equal if they were given the same default.
+    pub default_para_level: Option<Level>,
 }
 
 impl<'text> BidiInfo<'text> {
     /// Split the text into paragraphs and determine the bidi embedding levels for each paragraph.
     ///
+    /// Characters removed by rule X9 (`RLE`, `LRE`, `RLO`, `LRO`, `PDF` and `BN`) are skipped by
+    /// the weak and neutral rules instead of acting as barriers, so e.g. the `BN`s in
+    /// `EN BN ES BN EN` don't prevent rule W4 from applying.  Each of them is then given the level
+    /// of the preceding character, so that they don't break up level runs.
+    ///
     /// TODO: In early steps, check for special cases that allow later steps to be skipped. like
     /// text that is entirely LTR.  See the `nsBidi` class from Gecko for comparison.
     ///
     /// TODO: Support auto-RTL base direction
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn new(text: &str, default_para_level: Option<Level>) -> BidiInfo {
+        BidiInfo::new_with_initial_info(InitialInfo::new(text, default_para_level))
+    }
+
+    /// Split the text into paragraphs and determine the bidi embedding levels for each paragraph,
+    /// choosing the paragraph levels according to `mode`.
+    ///
+    /// See [`BidiInfo::new()`](#method.new) and [`ParaLevelMode`](enum.ParaLevelMode.html).
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_with_mode(text: &str, mode: ParaLevelMode) -> BidiInfo<'_> {
+        BidiInfo::new_with_initial_info(InitialInfo::new_with_mode(text, mode))
+    }
+
+    /// Like `BidiInfo::new()`, but accept anything that can be borrowed as a `str`, e.g. a
+    /// `String` or a `Cow<str>`.
+    ///
+    /// The result borrows the text, so `text` is taken by reference and must outlive it.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_from<S: AsRef<str> + ?Sized>(
+        text: &'text S,
+        default_para_level: Option<Level>,
+    ) -> BidiInfo<'text> {
+        BidiInfo::new(text.as_ref(), default_para_level)
+    }
+
+    /// Like `BidiInfo::new()`, but take the class of each character from `data_source` instead of
+    /// the data built into this crate.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_with_data_source<D: BidiDataSource + ?Sized>(
+        text: &'text str,
+        default_para_level: Option<Level>,
+        data_source: &D,
+    ) -> BidiInfo<'text> {
+        BidiInfo::new_with_initial_info(InitialInfo::new_with_classifier(
+            text,
+            default_para_level,
+            LTR_LEVEL,
+            |_, c| data_source.bidi_class(c),
+        ))
+    }
+
+    /// Like `BidiInfo::new()`, but with the class of the characters within each of the byte ranges
+    /// of `overrides` given instead of taken from the Unicode Character Database.
+    ///
+    /// This is useful for text that embeds objects or markup whose direction is known.  If the
+    /// ranges overlap, the last one wins.  Each range must be within the text, and start and end
+    /// at character boundaries, or `BidiError::InvalidOverrideRange` or
+    /// `BidiError::NonCharBoundary` is returned.
+    #[cfg_attr(feature = "flame_it", flame)]
+    #[must_use = "the errors of the analysis should be handled"]
+    pub fn new_with_class_overrides(
+        text: &'text str,
+        default_para_level: Option<Level>,
+        overrides: &[(Range<usize>, BidiClass)],
+    ) -> Result<BidiInfo<'text>, BidiError> {
+        for (range, _) in overrides {
+            if range.start > range.end || range.end > text.len() {
+                return Err(BidiError::InvalidOverrideRange { range: range.clone() });
+            }
+            for &index in &[range.start, range.end] {
+                if !text.is_char_boundary(index) {
+                    return Err(BidiError::NonCharBoundary { index });
+                }
+            }
+        }
+        let overrides = merge_overrides(overrides);
+        // The characters are classified in order, so the ranges they fall in are too.
+        let mut next = 0;
+        Ok(BidiInfo::new_with_initial_info(InitialInfo::new_with_classifier(
+            text,
+            default_para_level,
+            LTR_LEVEL,
+            |i, c| {
+                while next < overrides.len() && overrides[next].0.end <= i {
+                    next += 1;
+                }
+                match overrides.get(next) {
+                    Some(&(ref range, class)) if range.start <= i => class,
+                    _ => bidi_class(c),
+                }
+            },
+        )))
+    }
+
+    /// Like `BidiInfo::new()`, but with the U+FFFC OBJECT REPLACEMENT CHARACTER at each of the
+    /// byte offsets of `objects` displayed with the given direction.
+    ///
+    /// Such characters stand for inline objects, like images, in rich text.  They are neutral
+    /// (`ON`) on their own; `Some(ResolvedDirection::Ltr)` gives them the class `L` and
+    /// `Some(ResolvedDirection::Rtl)` the class `R`, while `None` leaves them neutral, so that the
+    /// surrounding text decides.  If an offset is not the start of a U+FFFC in the text,
+    /// `BidiError::NotObjectReplacementChar` is returned.
+    #[cfg_attr(feature = "flame_it", flame)]
+    #[must_use = "the errors of the analysis should be handled"]
+    pub fn new_with_object_directions(
+        text: &'text str,
+        default_para_level: Option<Level>,
+        objects: &[(usize, Option<ResolvedDirection>)],
+    ) -> Result<BidiInfo<'text>, BidiError> {
+        const OBJECT_REPLACEMENT_CHAR: char = '\u{FFFC}';
+
+        let mut overrides = Vec::with_capacity(objects.len());
+        for &(index, direction) in objects {
+            match text.get(index..) {
+                Some(rest) if rest.starts_with(OBJECT_REPLACEMENT_CHAR) => {}
+                _ => return Err(BidiError::NotObjectReplacementChar { index }),
+            }
+            let class = match direction {
+                Some(ResolvedDirection::Ltr) => L,
+                Some(ResolvedDirection::Rtl) => R,
+                None => ON,
+            };
+            overrides.push((index..index + OBJECT_REPLACEMENT_CHAR.len_utf8(), class));
+        }
+        BidiInfo::new_with_class_overrides(text, default_para_level, &overrides)
+    }
+
+    /// Split the text into lines and determine the bidi embedding levels for each line, with each
+    /// line getting its own base level from its first strong character (rules P2 and P3).
+    ///
+    /// This is the "plaintext" behavior used by some protocols.  Unlike `BidiInfo::new()`, the
+    /// line terminators that aren't paragraph separators (`B`) in the Unicode Character Database,
+    /// namely VT, FF and U+2028 LINE SEPARATOR, also end a line.  Each line is a separate entry of
+    /// `paragraphs`, and the terminators get the class `B` in `original_classes`.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_per_line_auto(text: &str) -> BidiInfo<'_> {
+        BidiInfo::new_with_initial_info(InitialInfo::new_with_classifier(
+            text,
+            None,
+            LTR_LEVEL,
+            |_, c| match c {
+                '\u{000B}' | '\u{000C}' | '\u{2028}' => B,
+                _ => bidi_class(c),
+            },
+        ))
+    }
+
+    /// Split the text into paragraphs and determine the bidi embedding levels for each paragraph,
+    /// using the given paragraph embedding levels instead of rules P2 and P3.
+    ///
+    /// `para_levels` must hold exactly one level per paragraph of `text`, in order, or
+    /// `BidiError::MismatchedLength` is returned.  A level deeper than
+    /// `Level::max_explicit_depth()` gives `BidiError::LevelTooDeep`.  This is useful when the
+    /// direction of each paragraph is already known, e.g. from markup.
+    #[cfg_attr(feature = "flame_it", flame)]
+    #[must_use = "the errors of the analysis should be handled"]
+    pub fn new_with_para_levels<'a>(
+        text: &'a str,
+        para_levels: &[Level],
+    ) -> Result<BidiInfo<'a>, BidiError> {
+        // Paragraph boundaries and classes don't depend on the paragraph levels, so a fixed level
+        // is given here only to skip rule P2.
+        let mut initial_info = InitialInfo::new(text, Some(LTR_LEVEL));
+        if initial_info.paragraphs.len() != para_levels.len() {
+            return Err(BidiError::MismatchedLength {
+                expected: initial_info.paragraphs.len(),
+                actual: para_levels.len(),
+            });
+        }
+        if let Some(&level) = para_levels
+            .iter()
+            .find(|level| level.number() > Level::max_explicit_depth())
+        {
+            return Err(BidiError::LevelTooDeep { level });
+        }
+        for (para, &level) in initial_info.paragraphs.iter_mut().zip(para_levels) {
+            para.level = level;
+        }
+        initial_info.default_para_level = None;
+        Ok(BidiInfo::new_with_initial_info(initial_info))
+    }
+
+    /// Like `BidiInfo::new()`, but also return the maximum values reached by the counters of the
+    /// explicit level resolution in any paragraph.
+    ///
+    /// This is meant for debugging, e.g. to find out whether a conformance test failure involves
+    /// the overflow rules.  See [`Diagnostics`](struct.Diagnostics.html).
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_with_diagnostics(
+        text: &str,
+        default_para_level: Option<Level>,
+    ) -> (BidiInfo<'_>, Diagnostics) {
+        let initial_info = InitialInfo::new(text, default_para_level);
+        BidiInfo::resolve(initial_info, ResolveOptions::default())
+    }
+
+    /// Like `BidiInfo::new()`, but also return every push and pop of the directional status stack
+    /// made by the explicit formatting characters (rules X2-X7), in the order they were applied.
+    ///
+    /// The index of each event is the byte index of its formatting character.  A PDI may pop
+    /// several entries, which are reported from the top of the stack down.  Formatting characters
+    /// ignored because of an overflow don't produce any event.
+    ///
+    /// This is meant for debugging and educational tools.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_with_stack_trace(
+        text: &str,
+        default_para_level: Option<Level>,
+    ) -> (BidiInfo<'_>, Vec<StackEvent>) {
+        let initial_info = InitialInfo::new(text, default_para_level);
+        let mut events = Vec::new();
+        let options = ResolveOptions {
+            stack_events: Some(&mut events),
+            ..ResolveOptions::default()
+        };
+        let (bidi_info, _) = BidiInfo::resolve(initial_info, options);
+        (bidi_info, events)
+    }
+
+    /// Like `BidiInfo::new()`, but also return every change of class made by the weak and neutral
+    /// rules (W1-W7, N1-N2), in the order they were applied.
+    ///
+    /// Only changes of a character's class are reported, with the byte index of its first byte.
+    /// The classes of characters removed by rule X9 are not changed by these rules.
+    ///
+    /// This is meant for debugging and educational tools.  Requires the `trace` feature.
+    #[cfg(feature = "trace")]
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_with_trace(
+        text: &str,
+        default_para_level: Option<Level>,
+    ) -> (BidiInfo<'_>, Vec<RuleEvent>) {
+        let mut trace = Trace::enabled();
+        let options = ResolveOptions {
+            trace: Some(&mut trace),
+            ..ResolveOptions::default()
+        };
+        let (bidi_info, _) = BidiInfo::resolve(InitialInfo::new(text, default_para_level), options);
+        let events = trace
+            .into_events()
+            .into_iter()
+            .filter(|event| text.is_char_boundary(event.index))
+            .collect();
+        (bidi_info, events)
+    }
+
+    /// Determine the bidi embedding levels for each paragraph found by `InitialInfo`.
+    fn new_with_initial_info(initial_info: InitialInfo<'text>) -> BidiInfo<'text> {
+        BidiInfo::resolve(initial_info, ResolveOptions::default()).0
+    }
+
+    /// Determine the bidi embedding levels for each paragraph found by `InitialInfo`, and collect
+    /// `Diagnostics` on the way, as well as whatever the `options` ask for.
+    fn resolve(
+        initial_info: InitialInfo<'text>,
+        options: ResolveOptions,
+    ) -> (BidiInfo<'text>, Diagnostics) {
+        let ResolveOptions {
+            trace,
+            mut stack_events,
+            neutral_fallback,
+        } = options;
+        let mut disabled_trace = Trace::disabled();
+        let trace = trace.unwrap_or(&mut disabled_trace);
+
         let InitialInfo {
+            text,
             original_classes,
             paragraphs,
-            ..
-        } = InitialInfo::new(text, default_para_level);
+            default_para_level,
+        } = initial_info;
 
         let mut levels = Vec::<Level>::with_capacity(text.len());
         let mut processing_classes = original_classes.clone();
+        let mut diagnostics = Diagnostics::default();
 
         for para in &paragraphs {
             let text = &text[para.range.clone()];
@@ -281,37 +744,293 @@ impl<'text> BidiInfo<'text> {
             levels.resize(new_len, para.level);
             let levels = &mut levels[para.range.clone()];
 
+            let first_event = stack_events.as_ref().map_or(0, |events| events.len());
+            let had_overflow = diagnostics.first_overflow_index.is_some();
             explicit::compute(
                 text,
                 para.level,
                 original_classes,
                 levels,
                 processing_classes,
+                &mut diagnostics,
+                stack_events.as_deref_mut(),
             );
+            if let Some(ref mut events) = stack_events {
+                for event in &mut events[first_event..] {
+                    event.index += para.range.start;
+                }
+            }
+            if !had_overflow {
+                if let Some(ref mut index) = diagnostics.first_overflow_index {
+                    *index += para.range.start;
+                }
+            }
 
             let sequences = prepare::isolating_run_sequences(para.level, original_classes, levels);
+            trace.set_paragraph_start(para.range.start);
             for sequence in &sequences {
-                implicit::resolve_weak(sequence, processing_classes);
-                implicit::resolve_neutral(sequence, levels, processing_classes);
+                implicit::resolve_weak(sequence, processing_classes, trace);
+                implicit::resolve_neutral(
+                    sequence,
+                    levels,
+                    processing_classes,
+                    neutral_fallback,
+                    trace,
+                );
             }
             implicit::resolve_levels(processing_classes, levels);
 
             assign_levels_to_removed_chars(para.level, original_classes, levels);
         }
 
-        BidiInfo {
+        let bidi_info = BidiInfo {
             text,
             original_classes,
             paragraphs,
             levels,
+            default_para_level,
+        };
+        (bidi_info, diagnostics)
+    }
+
+    /// Run the algorithm on a sequence of classes, without any text.
+    ///
+    /// Each class stands for one character, so the vectors of the result hold exactly one entry
+    /// per class, and paragraph ranges are indices into `classes`.  `B` separates paragraphs, and
+    /// each `FSI` is resolved to `LRI` or `RLI` as for text.  This is useful for tests, like the
+    /// ones of `BidiTest.txt`, and for engines that classify characters themselves.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn from_classes(
+        classes: &[BidiClass],
+        default_para_level: Option<Level>,
+    ) -> BidiInfoClasses {
+        let chars = classes.iter().enumerate().map(|(i, &class)| (i, 1, class));
+        let (original_classes, paragraphs) =
+            InitialInfo::split_paragraphs(chars, default_para_level, LTR_LEVEL);
+        // The algorithm only needs the text for its character boundaries, and each class stands
+        // for a single byte.
+        let text = " ".repeat(classes.len());
+        let initial_info = InitialInfo {
+            text: &text,
+            original_classes,
+            paragraphs,
+            default_para_level,
+        };
+        let BidiInfo {
+            original_classes,
+            levels,
+            paragraphs,
+            ..
+        } = BidiInfo::new_with_initial_info(initial_info);
+        BidiInfoClasses {
+            original_classes,
+            levels,
+            paragraphs,
+        }
+    }
+
+    /// Split Latin-1 (ISO-8859-1) encoded text into paragraphs and determine the bidi embedding
+    /// levels for each paragraph.
+    ///
+    /// Latin-1 maps each byte to the code point with the same value (U+0000..=U+00FF), so the
+    /// vectors of the result hold exactly one entry per byte of `text`, and paragraph ranges are
+    /// byte indices into `text`.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_latin1(text: &[u8], default_para_level: Option<Level>) -> Latin1BidiInfo<'_> {
+        let decoded: String = text.iter().map(|&b| char::from(b)).collect();
+        let bidi_info = BidiInfo::new(&decoded, default_para_level);
+
+        // The byte index within `decoded` of each Latin-1 character, and of the end of the text.
+        let offsets: Vec<usize> = decoded
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(decoded.len()))
+            .collect();
+        let latin1_index = |i: usize| offsets.binary_search(&i).expect("Not a char boundary");
+
+        Latin1BidiInfo {
+            text,
+            original_classes: offsets[..text.len()]
+                .iter()
+                .map(|&i| bidi_info.original_classes[i])
+                .collect(),
+            levels: offsets[..text.len()]
+                .iter()
+                .map(|&i| bidi_info.levels[i])
+                .collect(),
+            paragraphs: bidi_info
+                .paragraphs
+                .iter()
+                .map(|para| {
+                    ParagraphInfo {
+                        range: latin1_index(para.range.start)..latin1_index(para.range.end),
+                        level: para.level,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Split UTF-16 encoded text into paragraphs and determine the bidi embedding levels for each
+    /// paragraph.
+    ///
+    /// The vectors of the result hold one entry per code unit of `text`, and paragraph ranges are
+    /// code unit indices into `text`.  Both code units of a surrogate pair get the class and level
+    /// of their character.
+    ///
+    /// Unpaired surrogates are allowed, as in JavaScript strings (WTF-16), and are treated like
+    /// boundary neutrals (`BN`).  See
+    /// [`new_utf16_with_surrogates()`](#method.new_utf16_with_surrogates) to choose another class.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_utf16(text: &[u16], default_para_level: Option<Level>) -> Utf16BidiInfo<'_> {
+        BidiInfo::new_utf16_with_surrogates(text, default_para_level, UnpairedSurrogates::default())
+    }
+
+    /// Split UTF-16 encoded text into paragraphs and determine the bidi embedding levels for each
+    /// paragraph, treating unpaired surrogates as specified by `unpaired_surrogates`.
+    ///
+    /// See [`new_utf16()`](#method.new_utf16).
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn new_utf16_with_surrogates(
+        text: &[u16],
+        default_para_level: Option<Level>,
+        unpaired_surrogates: UnpairedSurrogates,
+    ) -> Utf16BidiInfo<'_> {
+        // Unpaired surrogates are replaced by a character of the chosen class, so that each code
+        // unit maps to a character of `decoded`.
+        let mut decoded = String::with_capacity(text.len());
+        // The byte index within `decoded` of the character of each code unit, and of the end of
+        // the text.
+        let mut offsets = Vec::with_capacity(text.len() + 1);
+        for result in std::char::decode_utf16(text.iter().cloned()) {
+            let (c, len) = match result {
+                Ok(c) => (c, c.len_utf16()),
+                Err(_) => (unpaired_surrogates.replacement_char(), 1),
+            };
+            for _ in 0..len {
+                offsets.push(decoded.len());
+            }
+            decoded.push(c);
+        }
+        offsets.push(decoded.len());
+
+        let bidi_info = BidiInfo::new(&decoded, default_para_level);
+        // The index of the first code unit at or after byte `i`.
+        let utf16_index = |i: usize| {
+            let search = offsets.binary_search_by(|&offset| if offset < i {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            });
+            search.unwrap_err()
+        };
+
+        Utf16BidiInfo {
+            text,
+            original_classes: offsets[..text.len()]
+                .iter()
+                .map(|&i| bidi_info.original_classes[i])
+                .collect(),
+            levels: offsets[..text.len()]
+                .iter()
+                .map(|&i| bidi_info.levels[i])
+                .collect(),
+            paragraphs: bidi_info
+                .paragraphs
+                .iter()
+                .map(|para| {
+                    ParagraphInfo {
+                        range: utf16_index(para.range.start)..utf16_index(para.range.end),
+                        level: para.level,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// The text
+    #[inline]
+    pub fn text(&self) -> &'text str {
+        self.text
+    }
+
+    /// The BidiClass of the character at each byte in the text.
+    #[inline]
+    pub fn original_classes(&self) -> &[BidiClass] {
+        &self.original_classes
+    }
+
+    /// The directional embedding level of each byte in the text.
+    #[inline]
+    pub fn levels(&self) -> &[Level] {
+        &self.levels
+    }
+
+    /// The boundaries and paragraph embedding level of each paragraph within the text.
+    #[inline]
+    pub fn paragraphs(&self) -> &[ParagraphInfo] {
+        &self.paragraphs
+    }
+
+    /// The text, classes and levels that the line methods work on.
+    fn resolved(&self) -> Resolved<'_, 'text> {
+        Resolved {
+            text: self.text,
+            original_classes: &self.original_classes,
+            levels: &self.levels,
         }
     }
 
+    /// Iterate over the characters of the text in logical order, along with their resolved level
+    /// and original class.
+    ///
+    /// This yields one item per character, while `levels` and `original_classes` have one entry
+    /// per byte.  Rule L1 is not applied, since it depends on line breaking.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (char, Level, BidiClass)> + 'a {
+        self.text
+            .char_indices()
+            .map(move |(i, c)| (c, self.levels[i], self.original_classes[i]))
+    }
+
     /// Re-order a line based on resolved levels and return only the embedding levels, one `Level`
     /// per *byte*.
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn reordered_levels(&self, para: &ParagraphInfo, line: Range<usize>) -> Vec<Level> {
-        let (levels, _) = self.visual_runs(para, line.clone());
+        self.resolved().reordered_levels(para, line)
+    }
+
+    /// Like `reordered_levels()`, with a choice of the level of the paragraph separator (`B`) at
+    /// the end of the line, if any.
+    ///
+    /// Rule L1 resets the paragraph separator to the paragraph level, which is what
+    /// `SeparatorLevel::ParagraphLevel` does.  Renderers that display the separator, e.g. as a
+    /// pilcrow, may want it to follow the text before it instead.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#L1>
+    pub fn reordered_levels_with_separator(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+        separator: SeparatorLevel,
+    ) -> Vec<Level> {
+        let mut levels = self.reordered_levels(para, line.clone());
+        if separator == SeparatorLevel::PrecedingRun {
+            if let Some((i, c)) = self.text[line.clone()].char_indices().next_back() {
+                let i = line.start + i;
+                if self.original_classes[i] == B {
+                    let level = (line.start..i)
+                        .rev()
+                        .find(|&j| {
+                            self.text.is_char_boundary(j) &&
+                                !prepare::removed_by_x9(self.original_classes[j])
+                        })
+                        .map_or(para.level, |j| levels[j]);
+                    for level_ref in &mut levels[i..i + c.len_utf8()] {
+                        *level_ref = level;
+                    }
+                }
+            }
+        }
         levels
     }
 
@@ -323,469 +1042,5023 @@ impl<'text> BidiInfo<'text> {
         para: &ParagraphInfo,
         line: Range<usize>,
     ) -> Vec<Level> {
-        let levels = self.reordered_levels(para, line);
-        self.text.char_indices().map(|(i, _)| levels[i]).collect()
+        self.resolved().reordered_levels_per_char(para, line)
     }
 
 
     /// Re-order a line based on resolved levels and return the line in display order.
+    ///
+    /// Combining marks (`NSM`) stay after their base character in RTL runs, as specified by rule
+    /// L3, so that they can be rendered on the reversed base.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#L3>
     #[cfg_attr(feature = "flame_it", flame)]
     pub fn reorder_line(&self, para: &ParagraphInfo, line: Range<usize>) -> Cow<'text, str> {
-        let (levels, runs) = self.visual_runs(para, line.clone());
+        self.resolved().reorder_line(para, line)
+    }
 
-        // If all isolating run sequences are LTR, no reordering is needed
-        if runs.iter().all(|run| levels[run.start].is_ltr()) {
-            return self.text[line.clone()].into();
+    /// Re-order several lines of a paragraph, like calling `reorder_line()` for each of them.
+    ///
+    /// This reuses the same buffers for the levels and runs of every line, which is faster than
+    /// separate calls when a paragraph is broken into many lines.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn reorder_lines(
+        &self,
+        para: &ParagraphInfo,
+        lines: &[Range<usize>],
+    ) -> Vec<Cow<'text, str>> {
+        let mut levels = Vec::new();
+        let mut runs = Vec::new();
+        lines
+            .iter()
+            .map(|line| {
+                self.resolved().visual_runs_into(para, line.clone(), &mut levels, &mut runs);
+                self.resolved().reorder_visual_runs(line.clone(), &levels, &runs)
+            })
+            .collect()
+    }
+
+    /// Split a paragraph into lines and lazily re-order each of them, like `reorder_line()`.
+    ///
+    /// Lines end after each VT, FF or U+2028 LINE SEPARATOR, the line terminators that don't
+    /// end a paragraph (see [`new_per_line_auto()`](#method.new_per_line_auto)), and at the end
+    /// of the paragraph.  Each line keeps its terminator, and is re-ordered only when the iterator
+    /// reaches it.
+    pub fn reordered_lines_iter<'a>(
+        &'a self,
+        para: &'a ParagraphInfo,
+    ) -> impl Iterator<Item = Cow<'text, str>> + 'a {
+        let para_end = para.range.end;
+        let mut line_start = para.range.start;
+        iter::from_fn(move || {
+            if line_start >= para_end {
+                return None;
+            }
+            let line_end = self.text[line_start..para_end]
+                .char_indices()
+                .find(|&(_, c)| matches!(c, '\u{000B}' | '\u{000C}' | '\u{2028}'))
+                .map_or(para_end, |(i, c)| line_start + i + c.len_utf8());
+            let line = line_start..line_end;
+            line_start = line_end;
+            Some(self.reorder_line(para, line))
+        })
+    }
+
+    /// Re-order a line like `reorder_line()`, treating the characters removed by rule X9
+    /// (`RLE`, `LRE`, `RLO`, `LRO`, `PDF` and `BN`) as given by `removed`.
+    ///
+    /// `reorder_line()` keeps these characters, which is the same as `RemovedChars::Keep`.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn reorder_line_with_removed(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+        removed: RemovedChars,
+    ) -> Cow<'text, str> {
+        if removed == RemovedChars::Keep {
+            return self.reorder_line(para, line);
         }
+        let (levels, runs) = self.resolved().line_visual_runs(para, line.clone());
 
         let mut result = String::with_capacity(line.len());
-        for run in runs {
-            if levels[run.start].is_rtl() {
-                result.extend(self.text[run].chars().rev());
-            } else {
-                result.push_str(&self.text[run]);
+        for (i, c, _) in self.resolved().visual_chars(line.start, &levels, &runs) {
+            if !prepare::removed_by_x9(self.original_classes[i]) {
+                result.push(c);
+            } else if let RemovedChars::Replace(replacement) = removed {
+                result.push(replacement);
             }
         }
         result.into()
     }
 
-    /// Find the level runs within a line and return them in visual order.
-    ///
-    /// `line` is a range of bytes indices within `levels`.
+    /// Re-order a line like `reorder_line()`, and also return the byte offset within the text of
+    /// the source character of each output character.
     ///
-    /// <http://www.unicode.org/reports/tr9/#Reordering_Resolved_Levels>
+    /// The offsets are given per output `char`, not per output byte.  Editors can use them to map
+    /// a position in the displayed line back to a position in the text, e.g. for hit-testing.
     #[cfg_attr(feature = "flame_it", flame)]
-    pub fn visual_runs(
+    pub fn reorder_line_with_offsets(
         &self,
         para: &ParagraphInfo,
         line: Range<usize>,
-    ) -> (Vec<Level>, Vec<LevelRun>) {
-        assert!(line.start <= self.levels.len());
-        assert!(line.end <= self.levels.len());
+    ) -> (String, Vec<usize>) {
+        let (levels, runs) = self.resolved().line_visual_runs(para, line.clone());
 
-        let mut levels = self.levels.clone();
+        let mut result = String::with_capacity(line.len());
+        let mut offsets = Vec::with_capacity(line.len());
+        for (i, c, _) in self.resolved().visual_chars(line.start, &levels, &runs) {
+            result.push(c);
+            offsets.push(i);
+        }
+        (result, offsets)
+    }
 
-        // Reset some whitespace chars to paragraph level.
-        // <http://www.unicode.org/reports/tr9/#L1>
-        let line_str: &str = &self.text[line.clone()];
-        let mut reset_from: Option<usize> = Some(0);
-        let mut reset_to: Option<usize> = None;
-        for (i, c) in line_str.char_indices() {
+    /// Re-order a line like `reorder_line()`, and return each character of the line in display
+    /// order along with its level, after applying rule L1.
+    ///
+    /// The level tells renderers whether to mirror the character or reverse its glyphs.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn reorder_line_with_levels(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> Vec<(char, Level)> {
+        let (levels, runs) = self.resolved().line_visual_runs(para, line.clone());
+
+        let mut result = Vec::with_capacity(line.len());
+        let chars = self.resolved().visual_chars(line.start, &levels, &runs);
+        result.extend(chars.map(|(_, c, level)| (c, level)));
+        result
+    }
+
+    /// The visual column of each character of a line, in logical order, after re-ordering it like
+    /// `reorder_line()`.
+    ///
+    /// This assumes that each character takes a single column, as in a monospaced terminal, so
+    /// the columns are a permutation of `0..n` for a line of `n` characters.  It is the inverse of
+    /// the permutation given by the offsets of `reorder_line_with_offsets()`.
+    pub fn visual_columns(&self, para: &ParagraphInfo, line: Range<usize>) -> Vec<usize> {
+        let starts: Vec<usize> = self.text[line.clone()]
+            .char_indices()
+            .map(|(i, _)| line.start + i)
+            .collect();
+        let (_, offsets) = self.reorder_line_with_offsets(para, line);
+
+        let mut columns = vec![0; starts.len()];
+        for (column, offset) in offsets.into_iter().enumerate() {
+            let index = starts.binary_search(&offset).expect("offset of a character start");
+            columns[index] = column;
+        }
+        columns
+    }
+
+    /// Re-order a line like `reorder_line()`, and mark its level runs, for debugging.
+    ///
+    /// Each run is written in display order and preceded by its level in brackets, and runs are
+    /// separated by `|`, e.g. `"[0]abc |[1]גבא"` for `"abc אבג"`.  The markers are not escaped, so
+    /// this is only meant for diagnostics, not for rendering.
+    pub fn debug_visual(&self, para: &ParagraphInfo, line: Range<usize>) -> String {
+        let (levels, runs) = self.visual_runs(para, line.clone());
+
+        let mut result = String::with_capacity(line.len() + 4 * runs.len());
+        for (i, run) in runs.into_iter().enumerate() {
+            let level = levels[run.start - line.start];
+            if i > 0 {
+                result.push('|');
+            }
+            result.push_str(&format!("[{}]", level.number()));
+            result.extend(self.resolved().run_chars(run, level).map(|(_, c)| c));
+        }
+        result
+    }
+
+    /// Re-order a line like `reorder_line()`, and return its characters in display order.
+    ///
+    /// This avoids encoding the result as UTF-8 for callers that process it character by
+    /// character.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn reorder_line_chars(&self, para: &ParagraphInfo, line: Range<usize>) -> Vec<char> {
+        let (levels, runs) = self.resolved().line_visual_runs(para, line.clone());
+
+        let mut result = Vec::with_capacity(line.len());
+        result.extend(self.resolved().visual_chars(line.start, &levels, &runs).map(|(_, c, _)| c));
+        result
+    }
+
+    /// Re-order a whole paragraph as a single line and return it in display order.
+    ///
+    /// This is the same as calling `reorder_line()` with the full range of the paragraph, so rule
+    /// L1 resets the trailing whitespace (and the paragraph separator, if any) at the end of the
+    /// paragraph to the paragraph level.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn reorder_paragraph(&self, para: &ParagraphInfo) -> Cow<'text, str> {
+        self.reorder_line(para, para.range.clone())
+    }
+
+    /// Re-order each paragraph as a single line, like `reorder_paragraph()`, and return them in
+    /// order.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn reorder_all_paragraphs(&self) -> Vec<Cow<'text, str>> {
+        self.paragraphs
+            .iter()
+            .map(|para| self.reorder_paragraph(para))
+            .collect()
+    }
+
+    /// Whether re-ordering a whole paragraph as a single line leaves it in logical order.
+    ///
+    /// This is true if every character of the paragraph has an even level after applying rule
+    /// L1, in which case `reorder_paragraph()` returns the paragraph unchanged without allocating.
+    /// A paragraph with RTL characters may still look the same once re-ordered (e.g. if it has a
+    /// single RTL character), but this returns false for it.
+    pub fn paragraph_is_identity(&self, para: &ParagraphInfo) -> bool {
+        // Walk the paragraph backwards, like `level_run_count()`, to apply rule L1 on the way.
+        // Characters removed by X9 take the level of a neighbor, unless they are all there is.
+        let mut reset = true;
+        let mut all_removed = true;
+        for (i, _) in self.text[para.range.clone()].char_indices().rev() {
+            let i = para.range.start + i;
             match self.original_classes[i] {
-                // Ignored by X9
                 RLE | LRE | RLO | LRO | PDF | BN => {}
-                // Segment separator, Paragraph separator
                 B | S => {
-                    assert_eq!(reset_to, None);
-                    reset_to = Some(i + c.len_utf8());
-                    if reset_from == None {
-                        reset_from = Some(i);
+                    reset = true;
+                    all_removed = false;
+                    if para.level.is_rtl() {
+                        return false;
                     }
                 }
-                // Whitespace, isolate formatting
-                WS | FSI | LRI | RLI | PDI => {
-                    if reset_from == None {
-                        reset_from = Some(i);
+                WS | FSI | LRI | RLI | PDI if reset => {
+                    all_removed = false;
+                    if para.level.is_rtl() {
+                        return false;
                     }
                 }
                 _ => {
-                    reset_from = None;
-                }
-            }
-            if let (Some(from), Some(to)) = (reset_from, reset_to) {
-                #[cfg_attr(feature = "cargo-clippy", allow(needless_range_loop))]
-                for j in from..to {
-                    levels[j] = para.level;
+                    reset = false;
+                    all_removed = false;
+                    if self.levels[i].is_rtl() {
+                        return false;
+                    }
                 }
-                reset_from = None;
-                reset_to = None;
-            }
-        }
-        if let Some(from) = reset_from {
-            #[cfg_attr(feature = "cargo-clippy", allow(needless_range_loop))]
-            for j in from..line_str.len() {
-                levels[j] = para.level;
             }
         }
+        !(all_removed && para.level.is_rtl())
+    }
 
-        // Find consecutive level runs.
-        let mut runs = Vec::new();
-        let mut start = line.start;
-        let mut run_level = levels[start];
-        let mut min_level = run_level;
-        let mut max_level = run_level;
+    /// Whether re-ordering each paragraph as a single line leaves the whole text in logical order,
+    /// i.e. whether `paragraph_is_identity()` is true for every paragraph.
+    pub fn is_whole_text_identity(&self) -> bool {
+        self.paragraphs.iter().all(|para| self.paragraph_is_identity(para))
+    }
 
-        for (i, &new_level) in levels.iter().enumerate().take(line.end).skip(start + 1) {
-            if new_level != run_level {
-                // End of the previous run, start of a new one.
-                runs.push(start..i);
-                start = i;
-                run_level = new_level;
-                min_level = min(run_level, min_level);
-                max_level = max(run_level, max_level);
+    /// Whether a paragraph contains characters removed by rule X9: embedding and override
+    /// formatting characters (LRE, RLE, LRO, RLO, PDF) and boundary neutrals (`BN`).
+    ///
+    /// If it doesn't, `reorder_line_with_removed()` returns the same as `reorder_line()` for any
+    /// policy, so callers can skip the handling of removed characters.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#X9>
+    pub fn has_removed_chars(&self, para: &ParagraphInfo) -> bool {
+        self.original_classes[para.range.clone()]
+            .iter()
+            .any(|&class| prepare::removed_by_x9(class))
+    }
+
+    /// The text of a paragraph without the characters removed by rule X9, and the resolved level
+    /// of each of its bytes.
+    ///
+    /// This is for callers that strip these characters before display, so that the levels don't
+    /// have to be filtered along with the text.  The levels are aligned with the returned text:
+    /// they have one entry per byte, like `levels`.  Rule L1 is not applied, since it depends on
+    /// line breaking.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#X9>
+    pub fn levels_without_removed(&self, para: &ParagraphInfo) -> (String, Vec<Level>) {
+        let mut text = String::with_capacity(para.range.len());
+        let mut levels = Vec::with_capacity(para.range.len());
+        for (i, c) in self.text[para.range.clone()].char_indices() {
+            let i = para.range.start + i;
+            if !prepare::removed_by_x9(self.original_classes[i]) {
+                text.push(c);
+                levels.extend_from_slice(&self.levels[i..i + c.len_utf8()]);
             }
         }
-        runs.push(start..line.end);
+        (text, levels)
+    }
 
-        let run_count = runs.len();
+    /// Whether inserting `s` at the byte offset `pos` only requires the inserted text to be
+    /// resolved, leaving the levels of the rest of the text unchanged.
+    ///
+    /// This is a cheap check for editors, before re-analyzing the text.  It is true if `pos` is
+    /// within a paragraph (or at the end of the last one, unless it ends with a paragraph
+    /// separator) and `s` has no character that could change the resolution of its neighbors, as
+    /// given by [`affects_neighbors()`](fn.affects_neighbors.html), i.e. only whitespace and
+    /// other neutrals.  It is false if a character next to `pos` has a weak class (`ES`, `CS`,
+    /// `ET`, `NSM`, `EN` or `AN`), since rules W1-W7 resolve those from their neighbors, e.g. a
+    /// space inserted in "1,2" separates the comma from the digits (W4).  Characters removed by
+    /// rule X9 are skipped to find the neighbors.  Note that this does not account for rule L1,
+    /// which depends on line breaking.
+    ///
+    /// Panics if `pos` is not a character boundary of the text.
+    pub fn edit_is_local(&self, pos: usize, s: &str) -> bool {
+        assert!(self.text.is_char_boundary(pos), "not a character boundary");
+        let in_paragraph = pos < self.text.len() ||
+            (!self.text.is_empty() && self.original_classes[self.text.len() - 1] != B);
+        let weak = |i: usize| matches!(self.original_classes[i], ES | CS | ET | NSM | EN | AN);
+        let kept = |i: &usize| !prepare::removed_by_x9(self.original_classes[*i]);
+        let before = self.text[..pos].char_indices().map(|(i, _)| i).rev().find(kept);
+        let after = self.text[pos..].char_indices().map(|(i, _)| pos + i).find(kept);
+        in_paragraph && !before.map_or(false, weak) && !after.map_or(false, weak) &&
+            !s.chars().any(affects_neighbors)
+    }
 
-        // Re-order the odd runs.
-        // <http://www.unicode.org/reports/tr9/#L2>
+    /// Find the level runs within a line and return them in visual order.
+    ///
+    /// `line` is a range of bytes indices within `levels`.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#Reordering_Resolved_Levels>
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn visual_runs(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> (Vec<Level>, Vec<LevelRun>) {
+        self.resolved().visual_runs(para, line)
+    }
+
+    /// The level runs within a line along with their levels, from the highest level to the
+    /// lowest, after applying rule L1.
+    ///
+    /// Runs with the same level are kept in visual order.  This is the order in which rule L2
+    /// considers them, for callers that process the levels from high to low.
+    pub fn runs_sorted_by_level(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> Vec<(Level, LevelRun)> {
+        let (levels, runs) = self.resolved().line_visual_runs(para, line.clone());
+        let mut sorted: Vec<(Level, LevelRun)> =
+            runs.into_iter().map(|run| (levels[run.start - line.start], run)).collect();
+        sorted.sort_by_key(|&(level, _)| Reverse(level));
+        sorted
+    }
 
-        // Stop at the lowest *odd* level.
-        min_level = min_level.new_lowest_ge_rtl().expect("Level error");
+    /// The characters of a line that renderers should display with a mirrored glyph, along with
+    /// their byte offsets and the char to display instead.
+    ///
+    /// These are the characters that have a `Bidi_Mirroring_Glyph` (see
+    /// [`bidi_mirroring_glyph()`](fn.bidi_mirroring_glyph.html)) and an odd (RTL) level after
+    /// applying rule L1, in logical order.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#L4>
+    pub fn mirror_positions(&self, para: &ParagraphInfo, line: Range<usize>) -> Vec<(usize, char)> {
+        let levels = self.resolved().line_levels(para, line.clone());
+        self.text[line.clone()]
+            .char_indices()
+            .map(|(i, c)| (line.start + i, c))
+            .filter(|&(i, _)| levels[i - line.start].is_rtl())
+            .filter_map(|(i, c)| bidi_mirroring_glyph(c).map(|mirror| (i, mirror)))
+            .collect()
+    }
 
-        while max_level >= min_level {
-            // Look for the start of a sequence of consecutive runs of max_level or higher.
-            let mut seq_start = 0;
-            while seq_start < run_count {
-                if self.levels[runs[seq_start].start] < max_level {
-                    seq_start += 1;
-                    continue;
-                }
+    /// The level run of a line that contains the byte at `byte_index`, along with its level,
+    /// after applying rule L1.
+    ///
+    /// The run is one of those returned by [`visual_runs()`](#method.visual_runs).  Returns `None`
+    /// if `byte_index` is outside of `line`.
+    pub fn run_at(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+        byte_index: usize,
+    ) -> Option<(Level, LevelRun)> {
+        if byte_index < line.start || byte_index >= line.end {
+            return None;
+        }
+        let (levels, runs) = self.resolved().line_visual_runs(para, line.clone());
+        runs.into_iter()
+            .find(|run| run.start <= byte_index && byte_index < run.end)
+            .map(|run| (levels[run.start - line.start], run))
+    }
 
-                // Found the start of a sequence. Now find the end.
-                let mut seq_end = seq_start + 1;
-                while seq_end < run_count {
-                    if self.levels[runs[seq_end].start] < max_level {
-                        break;
-                    }
-                    seq_end += 1;
-                }
+    /// The lowest and highest levels within a line, after applying rule L1.
+    ///
+    /// Rule L2 reverses the runs of every level from the highest one down to the lowest odd one.
+    /// If the line is empty, both levels are the paragraph level.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#L2>
+    pub fn level_bounds(&self, para: &ParagraphInfo, line: Range<usize>) -> (Level, Level) {
+        if line.start == line.end {
+            return (para.level, para.level);
+        }
+        min_max_level(&self.resolved().line_levels(para, line))
+    }
 
-                // Reverse the runs within this sequence.
-                runs[seq_start..seq_end].reverse();
+    /// The byte range of the whitespace at the end of a line, which rule L1 resets to the
+    /// paragraph level.
+    ///
+    /// This is the longest sequence of whitespace (`WS`), segment separators (`S`), isolate
+    /// formatting characters and paragraph separators (`B`) at the logical end of the line, along
+    /// with any characters removed by rule X9 after its start.  The range is empty (and starts at
+    /// `line.end`) if the line doesn't end with whitespace.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#L1>
+    pub fn trailing_whitespace_range(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> Range<usize> {
+        assert!(para.range.start <= line.start && line.end <= para.range.end);
 
-                seq_start = seq_end;
+        let mut start = line.end;
+        for (i, _) in self.text[line.clone()].char_indices().rev() {
+            let i = line.start + i;
+            match self.original_classes[i] {
+                WS | S | B | FSI | LRI | RLI | PDI | RLE | LRE | RLO | LRO | PDF | BN => start = i,
+                _ => break,
             }
-            max_level.lower(1).expect(
-                "Lowering embedding level below zero",
-            );
         }
 
-        (levels, runs)
+        // Characters removed by X9 are only reset if they follow some whitespace.
+        let trailing_start = start;
+        for (i, c) in self.text[trailing_start..line.end].char_indices() {
+            let i = trailing_start + i;
+            if !prepare::removed_by_x9(self.original_classes[i]) {
+                break;
+            }
+            start = i + c.len_utf8();
+        }
+        start..line.end
     }
 
-    /// If processed text has any computed RTL levels
+    /// The visual edge of a truncated line on which a truncation marker such as "…" belongs.
     ///
-    /// This information is usually used to skip re-ordering of text when no RTL level is present
-    #[inline]
-    pub fn has_rtl(&self) -> bool {
-        level::has_rtl(&self.levels)
+    /// If the content of the line, without its trailing whitespace, resolved to a single
+    /// direction, the marker goes on the edge where that content ends: the right for LTR and the
+    /// left for RTL.  If the content mixes directions, or is empty, the direction of the
+    /// paragraph decides.
+    pub fn ellipsis_side(&self, para: &ParagraphInfo, line: Range<usize>) -> VisualDirection {
+        let content = line.start..self.trailing_whitespace_range(para, line.clone()).start;
+        let levels = self.resolved().line_levels(para, line);
+        let mut chars = self.text[content].char_indices().map(|(i, _)| levels[i]);
+        let rtl = match chars.next() {
+            Some(first) if chars.all(|l| l.is_rtl() == first.is_rtl()) => first.is_rtl(),
+            _ => para.level.is_rtl(),
+        };
+        if rtl {
+            VisualDirection::Left
+        } else {
+            VisualDirection::Right
+        }
     }
-}
 
-/// Assign levels to characters removed by rule X9.
-///
-/// The levels assigned to these characters are not specified by the algorithm.  This function
-/// assigns each one the level of the previous character, to avoid breaking level runs.
-#[cfg_attr(feature = "flame_it", flame)]
-fn assign_levels_to_removed_chars(para_level: Level, classes: &[BidiClass], levels: &mut [Level]) {
-    for i in 0..levels.len() {
-        if prepare::removed_by_x9(classes[i]) {
-            levels[i] = if i > 0 { levels[i - 1] } else { para_level };
+    /// The number of level runs within a line, after applying rule L1.
+    ///
+    /// This is the number of runs returned by [`visual_runs()`](#method.visual_runs), but it is
+    /// computed without allocating.  An empty line has no runs.
+    pub fn level_run_count(&self, para: &ParagraphInfo, line: Range<usize>) -> usize {
+        if line.start == line.end {
+            return 0;
+        }
+
+        // Walk the line backwards, so that it is known whether whitespace is followed by a
+        // segment or paragraph separator, or by the end of the line (rule L1).  Characters
+        // removed by X9 take the level of a neighbor, so they never start a run and are skipped.
+        let mut count = 0;
+        let mut prev_level = None;
+        let mut reset = true;
+        for (i, _) in self.text[line.clone()].char_indices().rev() {
+            let i = line.start + i;
+            let level = match self.original_classes[i] {
+                RLE | LRE | RLO | LRO | PDF | BN => continue,
+                B | S => {
+                    reset = true;
+                    para.level
+                }
+                WS | FSI | LRI | RLI | PDI if reset => para.level,
+                WS | FSI | LRI | RLI | PDI => self.levels[i],
+                _ => {
+                    reset = false;
+                    self.levels[i]
+                }
+            };
+            if prev_level != Some(level) {
+                count += 1;
+                prev_level = Some(level);
+            }
         }
+        if count == 0 {
+            // Only characters removed by X9, which keep their levels.
+            let levels = &self.levels[line];
+            count = 1 + levels.windows(2).filter(|w| w[0] != w[1]).count();
+        }
+        count
     }
-}
 
+    /// Find the level runs within a line and iterate over them in visual order, along with their
+    /// levels.
+    ///
+    /// This is the same as [`visual_runs()`](#method.visual_runs), but each run carries its own
+    /// level, and an `orientation` hint that is `Orientation::Unspecified` until set by the caller.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn visual_runs_iter(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> impl Iterator<Item = Run> {
+        let line_start = line.start;
+        let (levels, runs) = self.resolved().line_visual_runs(para, line);
+        runs.into_iter()
+            .map(move |range| Run::new(range.clone(), levels[range.start - line_start]))
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Find the level runs within a line like `visual_runs_iter()`, and split them further at each
+    /// of the byte offsets of `extra_breaks`, e.g. where the attributes of rich text change.
+    ///
+    /// The runs are in visual order, so the pieces of an RTL run are in reverse logical order.
+    /// Breaks at the edges of runs, or outside of the line, have no effect.  The breaks don't
+    /// need to be sorted.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn runs_with_breaks(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+        extra_breaks: &[usize],
+    ) -> Vec<Run> {
+        let mut breaks = extra_breaks.to_vec();
+        breaks.sort_unstable();
+        breaks.dedup();
 
-    #[test]
-    fn test_initial_text_info() {
-        let text = "a1";
-        assert_eq!(
-            InitialInfo::new(text, None),
-            InitialInfo {
-                text,
-                original_classes: vec![L, EN],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..2,
-                        level: LTR_LEVEL,
-                    },
-                ],
+        let mut runs = Vec::new();
+        for run in self.visual_runs_iter(para, line) {
+            let mut pieces = Vec::new();
+            let mut start = run.range.start;
+            for &b in &breaks {
+                if start < b && b < run.range.end {
+                    pieces.push(Run::new(start..b, run.level));
+                    start = b;
+                }
             }
+            pieces.push(Run::new(start..run.range.end, run.level));
+            if run.level.is_rtl() {
+                pieces.reverse();
+            }
+            runs.extend(pieces);
+        }
+        runs
+    }
+
+    /// Find the level runs within a line, in both logical and visual order.
+    ///
+    /// Returns the runs in logical order, along with the visual order as indices into that list:
+    /// the `i`-th run to display is `runs[visual[i]]`.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn runs_logical_and_visual(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> (Vec<Run>, Vec<usize>) {
+        let (levels, visual_runs) = self.resolved().line_visual_runs(para, line.clone());
+
+        let mut runs: Vec<Run> = visual_runs
+            .iter()
+            .map(|range| Run::new(range.clone(), levels[range.start - line.start]))
+            .collect();
+        runs.sort_by_key(|run| run.range.start);
+
+        // Runs don't overlap, so each one is identified by its start.
+        let visual = visual_runs
+            .iter()
+            .map(|range| {
+                runs.binary_search_by_key(&range.start, |run| run.range.start)
+                    .expect("Run not found")
+            })
+            .collect();
+
+        (runs, visual)
+    }
+
+    /// Find the isolating run sequences of a paragraph, sorted by their first character.
+    ///
+    /// The sequences are computed from the explicit embedding levels (rules X1-X10), before the
+    /// implicit rules resolve the final `levels`.  The ranges of their runs are byte indices
+    /// within `self.text`.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#BD13>
+    pub fn isolating_run_sequences(&self, para: &ParagraphInfo) -> Vec<IsolatingRunSequence> {
+        let original_classes = &self.original_classes[para.range.clone()];
+        let mut levels = vec![para.level; para.range.len()];
+        let mut processing_classes = original_classes.to_vec();
+        explicit::compute(
+            &self.text[para.range.clone()],
+            para.level,
+            original_classes,
+            &mut levels,
+            &mut processing_classes,
+            &mut Diagnostics::default(),
+            None,
         );
 
-        let text = "غ א";
-        assert_eq!(
-            InitialInfo::new(text, None),
-            InitialInfo {
-                text,
-                original_classes: vec![AL, AL, WS, R, R],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..5,
-                        level: RTL_LEVEL,
-                    },
-                ],
+        let mut sequences = prepare::isolating_run_sequences(para.level, original_classes, &levels);
+        for sequence in &mut sequences {
+            for run in &mut sequence.runs {
+                *run = para.range.start + run.start..para.range.start + run.end;
             }
-        );
+        }
+        sequences.sort_by_key(|sequence| sequence.runs[0].start);
+        sequences
+    }
 
-        let text = "a\u{2029}b";
-        assert_eq!(
-            InitialInfo::new(text, None),
-            InitialInfo {
-                text,
-                original_classes: vec![L, B, B, B, L],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..4,
-                        level: LTR_LEVEL,
-                    },
-                    ParagraphInfo {
-                        range: 4..5,
-                        level: LTR_LEVEL,
-                    },
-                ],
+    /// How each character of a paragraph should be displayed if it's a digit: with European
+    /// digits, or Arabic-Indic digits.
+    ///
+    /// This has one entry per character of the paragraph.  It's `Some` for the European and
+    /// Arabic numbers (`EN` and `AN`), after rule W2 changed the European numbers preceded by
+    /// Arabic letters (`AL`) to Arabic numbers, and `None` for other characters.  Like W2, this
+    /// only looks back to the previous strong character within the same isolating run sequence.
+    /// Directional overrides are not taken into account, so the digits they contain keep their
+    /// shape.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#W2>
+    pub fn digit_shaping(&self, para: &ParagraphInfo) -> Vec<Option<DigitShape>> {
+        let mut shapes = vec![None; para.range.len()];
+        for sequence in self.isolating_run_sequences(para) {
+            // `sos` is either L or R.
+            let mut last_strong_is_al = false;
+            for i in sequence.runs.iter().flat_map(Clone::clone) {
+                match self.original_classes[i] {
+                    L | R => last_strong_is_al = false,
+                    AL => last_strong_is_al = true,
+                    EN if !last_strong_is_al => {
+                        shapes[i - para.range.start] = Some(DigitShape::European)
+                    }
+                    EN | AN => shapes[i - para.range.start] = Some(DigitShape::ArabicIndic),
+                    _ => {}
+                }
             }
-        );
+        }
+        self.text[para.range.clone()]
+            .char_indices()
+            .map(|(i, _)| shapes[i])
+            .collect()
+    }
 
-        let text = format!("{}א{}a", chars::FSI, chars::PDI);
-        assert_eq!(
-            InitialInfo::new(&text, None),
-            InitialInfo {
-                text: &text,
-                original_classes: vec![RLI, RLI, RLI, R, R, PDI, PDI, PDI, L],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..9,
-                        level: LTR_LEVEL,
-                    },
-                ],
+    /// Count the characters of a paragraph resolving to LTR and RTL levels, and its level runs.
+    ///
+    /// This uses the resolved levels as they are, before rule L1 is applied to any line.
+    pub fn direction_stats(&self, para: &ParagraphInfo) -> DirectionStats {
+        let mut stats = DirectionStats::default();
+        let mut prev_level = None;
+        for (i, _) in self.text[para.range.clone()].char_indices() {
+            let level = self.levels[para.range.start + i];
+            if level.is_rtl() {
+                stats.rtl_chars += 1;
+            } else {
+                stats.ltr_chars += 1;
             }
-        );
+            if prev_level != Some(level) {
+                stats.run_count += 1;
+                prev_level = Some(level);
+            }
+        }
+        stats
+    }
+
+    /// The overall shape of the analysis: its paragraph counts and highest level.
+    ///
+    /// This is meant for terse assertions in tests, without comparing whole vectors of levels.
+    pub fn summary(&self) -> BidiSummary {
+        BidiSummary {
+            paragraph_count: self.paragraphs.len(),
+            rtl_paragraph_count: self.paragraphs.iter().filter(|p| p.level.is_rtl()).count(),
+            max_level: self.levels.iter().cloned().max().unwrap_or(LTR_LEVEL),
+            has_rtl: self.has_rtl(),
+        }
+    }
+
+    /// If the characters at byte offsets `a` and `b` are in the same level run, i.e. all the
+    /// characters from one to the other, inclusive, have the same resolved level.
+    ///
+    /// The offsets may be given in any order.  Rule L1 is not applied, since it depends on line
+    /// breaking.
+    ///
+    /// Panics if either offset is not within the text.
+    pub fn same_level_run(&self, a: usize, b: usize) -> bool {
+        let (start, end) = (min(a, b), max(a, b));
+        let level = self.levels[start];
+        self.levels[start..end + 1].iter().all(|&l| l == level)
+    }
+
+    /// The paragraph level that was given to the constructor, if any, and the resulting level
+    /// of `para`.
+    ///
+    /// If no level was given, the level of `para` was determined by rules P2 and P3.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#The_Paragraph_Level>
+    pub fn paragraph_base_info(&self, para: &ParagraphInfo) -> (Option<Level>, Level) {
+        (self.default_para_level, para.level)
+    }
+
+    /// The byte offsets of the first and last strong characters (`L`, `R` or `AL`) of a
+    /// paragraph, if any.
+    ///
+    /// Like rule P2, this skips the characters between an isolate initiator and its matching PDI
+    /// (or the end of the paragraph), so the first one is the character that determines the
+    /// paragraph level when it isn't given.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#P2>
+    pub fn strong_bounds(&self, para: &ParagraphInfo) -> (Option<usize>, Option<usize>) {
+        let mut first = None;
+        let mut last = None;
+        let mut isolate_depth = 0usize;
+        for (i, _) in self.text[para.range.clone()].char_indices() {
+            let i = para.range.start + i;
+            match self.original_classes[i] {
+                L | R | AL if isolate_depth == 0 => {
+                    first = first.or(Some(i));
+                    last = Some(i);
+                }
+                RLI | LRI | FSI => isolate_depth += 1,
+                PDI => isolate_depth = isolate_depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        (first, last)
+    }
+
+    /// The level of an isolate initiator (LRI, RLI or FSI), and the embedding level of its
+    /// content.
+    ///
+    /// The initiator takes the embedding level of the text around it (rules X5a-X5c), so the first
+    /// level is its resolved level like for the characters around it.  The second one is the
+    /// embedding level that the initiator pushes on the directional status stack, before the
+    /// implicit rules raise some of its content (e.g. numbers) further.  If the initiator overflows
+    /// the maximum depth, its content keeps the embedding level of the initiator.
+    ///
+    /// Panics if the character at `initiator_index` isn't an isolate initiator of `para`.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#X5a>
+    pub fn isolate_levels(&self, para: &ParagraphInfo, initiator_index: usize) -> (Level, Level) {
+        assert!(para.range.start <= initiator_index && initiator_index < para.range.end);
+        assert!(
+            BidiClassSet::ISOLATE_INITIATORS.contains(self.original_classes[initiator_index]),
+            "not an isolate initiator"
+        );
+
+        // Rerun rules X1-X8 on the paragraph, since only their result is stored.
+        let original_classes = &self.original_classes[para.range.clone()];
+        let mut levels = vec![para.level; para.range.len()];
+        let mut processing_classes = original_classes.to_vec();
+        let mut events = Vec::new();
+        explicit::compute(
+            &self.text[para.range.clone()],
+            para.level,
+            original_classes,
+            &mut levels,
+            &mut processing_classes,
+            &mut Diagnostics::default(),
+            Some(&mut events),
+        );
+        let index = initiator_index - para.range.start;
+        let content_level = events
+            .iter()
+            .find(|event| event.index == index && event.kind == StackEventKind::Push)
+            .map_or(levels[index], |event| event.level);
+        (self.levels[initiator_index], content_level)
+    }
+
+    /// The direction of each character of the text, from the parity of its resolved level.
+    ///
+    /// This has one entry per character, not per byte.  Rule L1 is not applied, since it depends
+    /// on line breaking.
+    pub fn char_directions(&self) -> Vec<ResolvedDirection> {
+        self.text
+            .char_indices()
+            .map(|(i, _)| ResolvedDirection::of_level(self.levels[i]))
+            .collect()
+    }
+
+    /// The direction of a word, whose byte range is given by the caller, e.g. from a word
+    /// segmentation.
+    ///
+    /// This uses the first strong character of the word (`L`, or `R` and `AL`), like rule P2 does
+    /// for a paragraph, so that a word mixing scripts gets the direction of its start.  If the word
+    /// has no strong character (e.g. a number), this is the direction of the resolved level of its
+    /// first character.
+    ///
+    /// Panics if the word is empty, or doesn't start and end at character boundaries.
+    pub fn word_direction(&self, word: Range<usize>) -> ResolvedDirection {
+        assert!(word.start < word.end, "empty word");
+        assert!(self.text.is_char_boundary(word.start) && self.text.is_char_boundary(word.end));
+        let first_strong = self.text[word.clone()]
+            .char_indices()
+            .map(|(i, _)| self.original_classes[word.start + i])
+            .find(|&class| matches!(class, L | R | AL));
+        match first_strong {
+            Some(L) => ResolvedDirection::Ltr,
+            Some(_) => ResolvedDirection::Rtl,
+            None => ResolvedDirection::of_level(self.levels[word.start]),
+        }
+    }
+
+    /// The maximal spans of a paragraph whose characters have the same direction, from the parity
+    /// of their resolved levels, in logical order.
+    ///
+    /// Unlike level runs, spans of different even (or odd) levels are merged, e.g. a number at
+    /// level 2 next to LTR text at level 0.  Rule L1 is not applied, since it depends on line
+    /// breaking.
+    pub fn direction_islands(
+        &self,
+        para: &ParagraphInfo,
+    ) -> Vec<(ResolvedDirection, Range<usize>)> {
+        let mut islands: Vec<(ResolvedDirection, Range<usize>)> = Vec::new();
+        for i in para.range.clone() {
+            let dir = ResolvedDirection::of_level(self.levels[i]);
+            match islands.last_mut() {
+                Some(&mut (last_dir, ref mut range)) if last_dir == dir => range.end = i + 1,
+                _ => islands.push((dir, i..i + 1)),
+            }
+        }
+        islands
+    }
+
+    /// The directional formatting characters to insert before and after `span`, a range of bytes
+    /// of the text, to display it with the `desired` direction within its context.
+    ///
+    /// If the span is within a paragraph of the `desired` direction, outside of any embedding or
+    /// isolate, and has no strong character of the opposite direction nor any explicit formatting
+    /// character, it already displays with that direction, except for the neutrals at its ends,
+    /// which may follow the surrounding text.  A mark (`LRM` or `RLM`) on each side is enough to
+    /// keep them within the span.  Otherwise, the span has to be isolated: the result is an `LRI`
+    /// or an `RLI` and a `PDI`, like HTML's `<bdi dir>`.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#Markup_And_Formatting>
+    pub fn controls_to_force(
+        &self,
+        span: Range<usize>,
+        desired: ResolvedDirection,
+    ) -> (char, char) {
+        assert!(span.start <= span.end && span.end <= self.text.len());
+        assert!(self.text.is_char_boundary(span.start) && self.text.is_char_boundary(span.end));
+        let para = self
+            .paragraphs
+            .iter()
+            .find(|para| para.range.start <= span.start && span.end <= para.range.end);
+        let marks_suffice = para.map_or(false, |para| {
+            ResolvedDirection::of_level(para.level) == desired &&
+                self.text[span.clone()].char_indices().all(|(i, _)| {
+                    let i = span.start + i;
+                    match self.original_classes[i] {
+                        L => desired == ResolvedDirection::Ltr && self.levels[i] == para.level,
+                        R | AL => desired == ResolvedDirection::Rtl && self.levels[i] == para.level,
+                        RLE | LRE | RLO | LRO | PDF | RLI | LRI | FSI | PDI => false,
+                        _ => true,
+                    }
+                })
+        });
+        match (desired, marks_suffice) {
+            (ResolvedDirection::Ltr, true) => (chars::LRM, chars::LRM),
+            (ResolvedDirection::Rtl, true) => (chars::RLM, chars::RLM),
+            (ResolvedDirection::Ltr, false) => (chars::LRI, chars::PDI),
+            (ResolvedDirection::Rtl, false) => (chars::RLI, chars::PDI),
+        }
+    }
+
+    /// If processed text has any computed RTL levels
+    ///
+    /// This information is usually used to skip re-ordering of text when no RTL level is present
+    #[inline]
+    pub fn has_rtl(&self) -> bool {
+        level::has_rtl(&self.levels)
+    }
+
+    /// Shrink the capacity of the vectors of this `BidiInfo` as much as possible.
+    ///
+    /// This is useful for analyses kept in memory for a long time.
+    pub fn shrink_to_fit(&mut self) {
+        self.original_classes.shrink_to_fit();
+        self.levels.shrink_to_fit();
+        self.paragraphs.shrink_to_fit();
+    }
+
+    /// An estimate of the heap memory used by this `BidiInfo`, in bytes.
+    ///
+    /// This is the allocated capacity of `original_classes`, `levels` and `paragraphs`, which may
+    /// be more than their length (see `shrink_to_fit()`).  The text is borrowed, so it's not
+    /// included, and neither is the overhead of the allocator.
+    pub fn memory_footprint(&self) -> usize {
+        self.original_classes.capacity() * mem::size_of::<BidiClass>() +
+            self.levels.capacity() * mem::size_of::<Level>() +
+            self.paragraphs.capacity() * mem::size_of::<ParagraphInfo>()
+    }
+
+    /// Check that the public fields of this `BidiInfo` are consistent with each other.
+    ///
+    /// This is always the case for a `BidiInfo` returned by a constructor, but not necessarily
+    /// after its fields were modified or supplied by the caller.  The checks are, in order:
+    ///
+    /// * `original_classes` and `levels` have one entry per byte of the text
+    ///   (`BidiError::MismatchedLength`),
+    /// * the paragraph levels are at most `Level::max_explicit_depth()`, and the other levels at
+    ///   most `Level::max_implicit_depth()` (`BidiError::LevelTooDeep`),
+    /// * `paragraphs` covers the whole text, in order and without gaps or overlaps
+    ///   (`BidiError::InvalidParagraphRange`), and splits it at character boundaries
+    ///   (`BidiError::NonCharBoundary`),
+    /// * each directional formatting character has its own class, except that an FSI may have
+    ///   been resolved to LRI or RLI (`BidiError::InconsistentClass`).
+    #[must_use = "the errors of the validation should be handled"]
+    pub fn validate(&self) -> Result<(), BidiError> {
+        for len in &[self.original_classes.len(), self.levels.len()] {
+            if *len != self.text.len() {
+                return Err(BidiError::MismatchedLength {
+                    expected: self.text.len(),
+                    actual: *len,
+                });
+            }
+        }
+
+        let paragraph_levels = self.paragraphs.iter().map(|para| para.level);
+        if let Some(level) = paragraph_levels
+            .filter(|level| level.number() > Level::max_explicit_depth())
+            .chain(self.levels.iter().cloned().filter(
+                |level| level.number() > Level::max_implicit_depth(),
+            ))
+            .next()
+        {
+            return Err(BidiError::LevelTooDeep { level });
+        }
+
+        let mut end = 0;
+        for (index, para) in self.paragraphs.iter().enumerate() {
+            if para.range.start != end || para.range.end <= para.range.start ||
+                para.range.end > self.text.len()
+            {
+                return Err(BidiError::InvalidParagraphRange { index });
+            }
+            if !self.text.is_char_boundary(para.range.end) {
+                return Err(BidiError::NonCharBoundary { index: para.range.end });
+            }
+            end = para.range.end;
+        }
+        if end != self.text.len() {
+            return Err(BidiError::InvalidParagraphRange { index: self.paragraphs.len() });
+        }
+
+        for (index, c) in self.text.char_indices() {
+            if !chars::is_explicit_format_char(c) {
+                continue;
+            }
+            let class = self.original_classes[index];
+            let consistent = match c {
+                chars::FSI => class == FSI || class == LRI || class == RLI,
+                _ => class == bidi_class(c),
+            };
+            if !consistent {
+                return Err(BidiError::InconsistentClass { index });
+            }
+        }
+        Ok(())
+    }
+
+    /// The index in `self.paragraphs` of the paragraph that contains the byte at `byte_index`.
+    ///
+    /// The end of the text, e.g. a cursor after the last character, belongs to the last
+    /// paragraph.  Panics if `byte_index` is greater than the length of the text, or if the text
+    /// is empty, since it has no paragraphs then.
+    pub fn paragraph_at(&self, byte_index: usize) -> usize {
+        assert!(byte_index <= self.text.len(), "byte index out of bounds");
+        assert!(!self.paragraphs.is_empty(), "no paragraphs");
+        if byte_index == self.text.len() {
+            return self.paragraphs.len() - 1;
+        }
+        self.paragraphs
+            .binary_search_by(|para| if para.range.end <= byte_index {
+                Ordering::Less
+            } else if para.range.start > byte_index {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            })
+            .expect("The paragraphs cover the text")
+    }
+
+    /// The indices of the paragraphs in `self.paragraphs`, in display order.
+    ///
+    /// This is always the logical order `0, 1, 2, ...`: the algorithm splits the text into
+    /// independent paragraphs (rule P1) and never reorders them, so paragraphs flow from top to
+    /// bottom even if some or all of them are RTL.  Layouts that place paragraphs side by side,
+    /// e.g. as columns, should order the columns themselves according to the direction of the
+    /// whole document (right to left for an RTL document), which is outside the scope of this
+    /// crate.
+    pub fn paragraph_display_order(&self) -> Vec<usize> {
+        (0..self.paragraphs.len()).collect()
+    }
+
+    /// Iterate over the paragraphs of the text, in logical order.
+    pub fn paragraphs_iter<'a>(&'a self) -> impl Iterator<Item = Paragraph<'a, 'text>> + 'a {
+        self.paragraphs.iter().map(move |para| Paragraph::new(self, para))
+    }
+
+    /// A JSON document with the text, paragraphs, levels and classes, for bug reports and for
+    /// comparisons with other implementations.
+    ///
+    /// The document is an object with the keys `"text"`, `"paragraphs"` (objects with the keys
+    /// `"start"`, `"end"` and `"level"`), `"levels"` (numbers) and `"classes"` (short names, e.g.
+    /// `"AL"`), in this order, on a single line.  Like the fields of `BidiInfo`, `"levels"` and
+    /// `"classes"` hold one entry per byte of the text.  This doesn't need the `serde` feature.
+    pub fn to_debug_json(&self) -> String {
+        let mut json = String::from("{\"text\":");
+        push_json_string(&mut json, self.text);
+        json.push_str(",\"paragraphs\":[");
+        for (i, para) in self.paragraphs.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!(
+                "{{\"start\":{},\"end\":{},\"level\":{}}}",
+                para.range.start,
+                para.range.end,
+                para.level.number()
+            ));
+        }
+        json.push_str("],\"levels\":[");
+        let levels: Vec<String> = self.levels.iter().map(|l| l.number().to_string()).collect();
+        json.push_str(&levels.join(","));
+        json.push_str("],\"classes\":[");
+        let classes: Vec<String> = self.original_classes
+            .iter()
+            .map(|class| format!("\"{}\"", class.short_name()))
+            .collect();
+        json.push_str(&classes.join(","));
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Append `s` to `json` as a JSON string literal.
+fn push_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            // Escape the other control characters, and the directional formatting characters so
+            // that the document displays in logical order.
+            c if c < ' ' || chars::is_explicit_format_char(c) => {
+                json.push_str(&format!("\\u{:04X}", c as u32))
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Builder for `BidiInfo`, for options that `BidiInfo::new()` doesn't take.
+///
+/// ```rust
+/// use unicode_bidi::{BidiClass, BidiInfoBuilder, ParaLevelMode};
+///
+/// let bidi_info = BidiInfoBuilder::new()
+///     .para_level_mode(ParaLevelMode::Auto)
+///     .with_pua_class(BidiClass::R)
+///     .build("\u{E000}\u{E001}");
+/// assert!(bidi_info.paragraphs[0].level.is_rtl());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BidiInfoBuilder {
+    mode: ParaLevelMode,
+    pua_class: Option<BidiClass>,
+    error_on_overflow: bool,
+    neutral_fallback: Option<BidiClass>,
+}
+
+impl BidiInfoBuilder {
+    /// A builder with the default options, which give the same result as `BidiInfo::new()` with
+    /// `None` as the paragraph level.
+    pub fn new() -> BidiInfoBuilder {
+        BidiInfoBuilder::default()
+    }
+
+    /// Choose the paragraph levels according to `mode`.  Defaults to `ParaLevelMode::Auto`.
+    pub fn para_level_mode(mut self, mode: ParaLevelMode) -> BidiInfoBuilder {
+        self.mode = mode;
+        self
+    }
+
+    /// Treat every Private Use Area code point (U+E000..=U+F8FF, U+F0000..=U+FFFFD and
+    /// U+100000..=U+10FFFD) as having the class `class`, instead of `L`.
+    ///
+    /// This is useful for applications that use the PUA for custom scripts, e.g. right-to-left
+    /// ones.
+    pub fn with_pua_class(mut self, class: BidiClass) -> BidiInfoBuilder {
+        self.pua_class = Some(class);
+        self
+    }
+
+    /// Make `try_build()` fail with `BidiError::IsolateOverflow` if the text nests embeddings or
+    /// isolates deeper than `max_depth`, instead of ignoring the formatting characters that
+    /// overflow as the algorithm specifies.  Defaults to `false`.
+    ///
+    /// `build()` can't report the error, so it panics if this is set.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#X5a>
+    pub fn error_on_overflow(mut self, error_on_overflow: bool) -> BidiInfoBuilder {
+        self.error_on_overflow = error_on_overflow;
+        self
+    }
+
+    /// Resolve the neutrals that rule N1 leaves unresolved, e.g. between characters of opposite
+    /// directions, to `direction` instead of the embedding direction (rule N2).
+    ///
+    /// This is a tailoring of the algorithm.  `None` restores the default, which follows the
+    /// algorithm.
+    ///
+    /// <http://www.unicode.org/reports/tr9/#N2>
+    pub fn neutral_fallback(mut self, direction: Option<ResolvedDirection>) -> BidiInfoBuilder {
+        self.neutral_fallback = direction.map(|direction| match direction {
+            ResolvedDirection::Ltr => L,
+            ResolvedDirection::Rtl => R,
+        });
+        self
+    }
+
+    /// Split the text into paragraphs and determine the bidi embedding levels for each paragraph,
+    /// using the options of this builder.
+    ///
+    /// Formatting characters that overflow the directional status stack are ignored, as the
+    /// algorithm specifies.
+    ///
+    /// Panics if `error_on_overflow(true)` was set, whether or not the text overflows.  Use
+    /// `try_build()` to get the error instead.
+    #[cfg_attr(feature = "flame_it", flame)]
+    pub fn build(self, text: &str) -> BidiInfo<'_> {
+        assert!(!self.error_on_overflow, "build() can't report overflows, use try_build()");
+        let initial_info = self.initial_info(text);
+        let options = ResolveOptions {
+            neutral_fallback: self.neutral_fallback,
+            ..ResolveOptions::default()
+        };
+        BidiInfo::resolve(initial_info, options).0
+    }
+
+    /// Like `build()`, but fail if the text overflows the directional status stack and
+    /// `error_on_overflow(true)` was set.
+    #[cfg_attr(feature = "flame_it", flame)]
+    #[must_use = "the text may have overflowed"]
+    pub fn try_build(self, text: &str) -> Result<BidiInfo<'_>, BidiError> {
+        let options = ResolveOptions {
+            neutral_fallback: self.neutral_fallback,
+            ..ResolveOptions::default()
+        };
+        let (bidi_info, diagnostics) = BidiInfo::resolve(self.initial_info(text), options);
+        match diagnostics.first_overflow_index {
+            Some(index) if self.error_on_overflow => Err(BidiError::IsolateOverflow { index }),
+            _ => Ok(bidi_info),
+        }
+    }
+
+    fn initial_info(self, text: &str) -> InitialInfo<'_> {
+        let pua_class = self.pua_class;
+        let class_of = |_, c: char| match pua_class {
+            Some(class) if char_data::is_private_use(c) => class,
+            _ => bidi_class(c),
+        };
+        InitialInfo::new_with_classifier(
+            text,
+            self.mode.default_para_level(),
+            self.mode.fallback_level(),
+            class_of,
+        )
+    }
+}
+
+/// Bidi information of a Latin-1 (ISO-8859-1) encoded text.
+///
+/// Created by [`BidiInfo::new_latin1()`](struct.BidiInfo.html#method.new_latin1).  Since every
+/// Latin-1 character is a single byte, the `original_classes` and `levels` vectors hold exactly one
+/// entry per character.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Latin1BidiInfo<'text> {
+    /// The text, as Latin-1 bytes
+    pub text: &'text [u8],
+
+    /// The BidiClass of the character at each byte in the text.
+    pub original_classes: Vec<BidiClass>,
+
+    /// The directional embedding level of each byte in the text.
+    pub levels: Vec<Level>,
+
+    /// The boundaries and paragraph embedding level of each paragraph within the text.
+    pub paragraphs: Vec<ParagraphInfo>,
+}
+
+impl<'text> Latin1BidiInfo<'text> {
+    /// If processed text has any computed RTL levels
+    ///
+    /// This information is usually used to skip re-ordering of text when no RTL level is present
+    #[inline]
+    pub fn has_rtl(&self) -> bool {
+        level::has_rtl(&self.levels)
+    }
+}
+
+/// Bidi information of a sequence of classes, without any text.
+///
+/// Created by [`BidiInfo::from_classes()`](struct.BidiInfo.html#method.from_classes).  The vectors
+/// hold exactly one entry per class.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BidiInfoClasses {
+    /// The classes, with each `FSI` resolved to `LRI` or `RLI` if possible.
+    pub original_classes: Vec<BidiClass>,
+
+    /// The directional embedding level of each class.
+    pub levels: Vec<Level>,
+
+    /// The boundaries and paragraph embedding level of each paragraph, as indices into the
+    /// classes.
+    pub paragraphs: Vec<ParagraphInfo>,
+}
+
+impl BidiInfoClasses {
+    /// If processed classes have any computed RTL levels
+    ///
+    /// This information is usually used to skip re-ordering when no RTL level is present
+    #[inline]
+    pub fn has_rtl(&self) -> bool {
+        level::has_rtl(&self.levels)
+    }
+}
+
+/// How to classify the unpaired surrogates of UTF-16 text.
+///
+/// See [`BidiInfo::new_utf16_with_surrogates()`][1].
+///
+/// [1]: struct.BidiInfo.html#method.new_utf16_with_surrogates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnpairedSurrogates {
+    /// Treat them as boundary neutrals (`BN`), which are removed by rule X9 and get the level of
+    /// the preceding character.  This is the default.
+    AsBoundaryNeutral,
+    /// Treat them as other neutrals (`ON`), like U+FFFD REPLACEMENT CHARACTER, which they are
+    /// often replaced with.
+    AsOtherNeutral,
+}
+
+impl Default for UnpairedSurrogates {
+    fn default() -> UnpairedSurrogates {
+        UnpairedSurrogates::AsBoundaryNeutral
+    }
+}
+
+impl UnpairedSurrogates {
+    /// A character with the class to use for unpaired surrogates.
+    fn replacement_char(self) -> char {
+        match self {
+            // ZERO WIDTH NO-BREAK SPACE
+            UnpairedSurrogates::AsBoundaryNeutral => '\u{FEFF}',
+            // REPLACEMENT CHARACTER
+            UnpairedSurrogates::AsOtherNeutral => '\u{FFFD}',
+        }
+    }
+}
+
+/// Bidi information of a UTF-16 encoded text.
+///
+/// Created by [`BidiInfo::new_utf16()`](struct.BidiInfo.html#method.new_utf16).  The
+/// `original_classes` and `levels` vectors are indexed by code units of the text.  If a character
+/// is a surrogate pair, then its class and level will appear twice in these vectors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Utf16BidiInfo<'text> {
+    /// The text, as UTF-16 code units
+    pub text: &'text [u16],
+
+    /// The BidiClass of the character at each code unit in the text.
+    pub original_classes: Vec<BidiClass>,
+
+    /// The directional embedding level of each code unit in the text.
+    pub levels: Vec<Level>,
+
+    /// The boundaries and paragraph embedding level of each paragraph within the text.
+    pub paragraphs: Vec<ParagraphInfo>,
+}
+
+impl<'text> Utf16BidiInfo<'text> {
+    /// If processed text has any computed RTL levels
+    ///
+    /// This information is usually used to skip re-ordering of text when no RTL level is present
+    #[inline]
+    pub fn has_rtl(&self) -> bool {
+        level::has_rtl(&self.levels)
+    }
+}
+
+/// Bidi information of an owned text.
+///
+/// This holds the same information as `BidiInfo`, but owns its text, so that it can be stored
+/// or shared independently of the text, e.g. by [`IncrementalBidi`](struct.IncrementalBidi.html).
+#[derive(Clone, Debug, PartialEq)]
+pub struct BidiInfoBuf {
+    /// The text
+    pub text: String,
+
+    /// The BidiClass of the character at each byte in the text.
+    pub original_classes: Vec<BidiClass>,
+
+    /// The directional embedding level of each byte in the text.
+    pub levels: Vec<Level>,
+
+    /// The boundaries and paragraph embedding level of each paragraph within the text.
+    pub paragraphs: Vec<ParagraphInfo>,
+
+    /// The paragraph level that was given instead of applying rules P2 and P3, if any, like
+    /// `BidiInfo::default_para_level`.
+    pub default_para_level: Option<Level>,
+}
+
+impl BidiInfoBuf {
+    /// Split the text into paragraphs and determine the bidi embedding levels for each paragraph,
+    /// like `BidiInfo::new()`.
+    pub fn new(text: String, default_para_level: Option<Level>) -> BidiInfoBuf {
+        let BidiInfo {
+            original_classes,
+            levels,
+            paragraphs,
+            default_para_level,
+            ..
+        } = BidiInfo::new(&text, default_para_level);
+        BidiInfoBuf {
+            text,
+            original_classes,
+            levels,
+            paragraphs,
+            default_para_level,
+        }
+    }
+
+    /// A `BidiInfo` borrowing the text of this one, to use its other methods.
+    ///
+    /// The vectors of classes, levels and paragraphs are cloned, so prefer the methods of
+    /// `BidiInfoBuf` where they exist.
+    pub fn bidi_info(&self) -> BidiInfo<'_> {
+        BidiInfo {
+            text: &self.text,
+            original_classes: self.original_classes.clone(),
+            levels: self.levels.clone(),
+            paragraphs: self.paragraphs.clone(),
+            default_para_level: self.default_para_level,
+        }
+    }
+
+    /// Re-order a line based on resolved levels and return only the embedding levels, one `Level`
+    /// per *byte*, like `BidiInfo::reordered_levels()`.
+    pub fn reordered_levels(&self, para: &ParagraphInfo, line: Range<usize>) -> Vec<Level> {
+        self.resolved().reordered_levels(para, line)
+    }
+
+    /// Re-order a line based on resolved levels and return only the embedding levels, one `Level`
+    /// per *character*, like `BidiInfo::reordered_levels_per_char()`.
+    pub fn reordered_levels_per_char(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> Vec<Level> {
+        self.resolved().reordered_levels_per_char(para, line)
+    }
+
+    /// Re-order a line based on resolved levels and return the line in display order, like
+    /// `BidiInfo::reorder_line()`.
+    pub fn reorder_line(&self, para: &ParagraphInfo, line: Range<usize>) -> Cow<'_, str> {
+        self.resolved().reorder_line(para, line)
+    }
+
+    /// Find the level runs within a line and return them in visual order, like
+    /// `BidiInfo::visual_runs()`.
+    pub fn visual_runs(
+        &self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> (Vec<Level>, Vec<LevelRun>) {
+        self.resolved().visual_runs(para, line)
+    }
+
+    /// If processed text has any computed RTL levels
+    ///
+    /// This information is usually used to skip re-ordering of text when no RTL level is present
+    #[inline]
+    pub fn has_rtl(&self) -> bool {
+        level::has_rtl(&self.levels)
+    }
+
+    /// The text, classes and levels that the line methods work on.
+    fn resolved(&self) -> Resolved<'_, '_> {
+        Resolved {
+            text: &self.text,
+            original_classes: &self.original_classes,
+            levels: &self.levels,
+        }
+    }
+}
+
+impl<'text> From<BidiInfo<'text>> for BidiInfoBuf {
+    fn from(bidi_info: BidiInfo<'text>) -> BidiInfoBuf {
+        BidiInfoBuf {
+            text: bidi_info.text.to_owned(),
+            original_classes: bidi_info.original_classes,
+            levels: bidi_info.levels,
+            paragraphs: bidi_info.paragraphs,
+            default_para_level: bidi_info.default_para_level,
+        }
+    }
+}
+
+/// Whether the text may need any bidi processing at all.
+///
+/// Returns `false` if every character in `text` has one of the classes `L`, `EN`, `ES`, `ET`,
+/// `CS`, `B`, `S`, `WS` or `ON`.  Such text resolves entirely to level 0 unless an RTL paragraph
+/// level is forced, so callers can skip building a `BidiInfo` for it.
+/// Otherwise (for any RTL character, Arabic number, explicit formatting character, etc.) returns
+/// `true`.  The scan stops at the first such character.
+pub fn requires_bidi(text: &str) -> bool {
+    chars_require_bidi(text.chars())
+}
+
+fn chars_require_bidi<I: IntoIterator<Item = char>>(chars: I) -> bool {
+    chars.into_iter().any(|c| {
+        !matches!(bidi_class(c), L | EN | ES | ET | CS | B | S | WS | ON)
+    })
+}
+
+/// Whether inserting or removing `c` may change the resolved levels of other characters.
+///
+/// This is a heuristic for editors, to find the edits that only need the edited character to be
+/// laid out again.  It returns `false` only for the neutral classes `WS` and `ON`, whose own level
+/// is resolved from their neighbors (rules N1 and N2) without changing them.  It returns `true`
+/// for everything else:
+///
+/// * strong classes (`L`, `R`, `AL`), which determine the resolution of neutrals and numbers
+///   around them, and possibly the paragraph level (P2),
+/// * weak classes, which take part in rules W1-W7 along with their neighbors,
+/// * explicit formatting characters, which change the embedding levels of what follows them,
+/// * the separators `B` and `S`, which end a paragraph or reset the levels before them (L1).
+///
+/// It is only an approximation: removing a space or a punctuation mark can still change how its
+/// neighbors are resolved, e.g. by joining two numbers with a separator (W4), or by moving a
+/// whitespace character to the end of a line (L1).
+pub fn affects_neighbors(c: char) -> bool {
+    !matches!(bidi_class(c), WS | ON)
+}
+
+/// Which kinds of RTL script are present in a text, as returned by
+/// [`rtl_scripts_present()`](fn.rtl_scripts_present.html).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RtlScripts {
+    /// Characters of class `R` in the Hebrew blocks.
+    pub hebrew: bool,
+    /// Characters of class `AL` or `AN`, which are mostly Arabic.
+    pub arabic: bool,
+    /// Characters of class `R` outside of the Hebrew blocks, e.g. N'Ko or Samaritan.
+    pub other: bool,
+}
+
+/// Find which kinds of RTL script are present in `text`, e.g. to select fonts.
+///
+/// This is only a heuristic over the bidi class and the block of each character, not the Unicode
+/// Script property.  Characters of class `AL` and `AN` count as Arabic, although some of them are
+/// Syriac, Thaana or other scripts that use Arabic letters or numbers.  Characters of class `R`
+/// count as Hebrew if they are in the Hebrew (U+0590..U+05FF) or Alphabetic Presentation Forms
+/// (U+FB1D..U+FB4F) blocks, and as other RTL otherwise.
+pub fn rtl_scripts_present(text: &str) -> RtlScripts {
+    let mut scripts = RtlScripts::default();
+    for c in text.chars() {
+        match bidi_class(c) {
+            R => {
+                if matches!(c, '\u{0590}'..='\u{05FF}' | '\u{FB1D}'..='\u{FB4F}') {
+                    scripts.hebrew = true;
+                } else {
+                    scripts.other = true;
+                }
+            }
+            AL | AN => scripts.arabic = true,
+            _ => {}
+        }
+    }
+    scripts
+}
+
+/// Find the First Strong Isolates (FSI) whose direction defaulted to LTR, because there is no
+/// strong character between them and their matching PDI.
+///
+/// Returns the byte range of each such isolate, from its FSI to the end of its matching PDI.  If
+/// the FSI has no matching PDI, the range ends at the end of its paragraph (before the paragraph
+/// separator, if any).  Strong characters within nested isolates don't count, as specified by rule
+/// X5c.
+///
+/// <http://www.unicode.org/reports/tr9/#X5c>
+pub fn weak_fsi_spans(text: &str) -> Vec<Range<usize>> {
+    // `InitialInfo` already changed the class of each FSI with a strong character to LRI or RLI.
+    let initial_info = InitialInfo::new(text, None);
+    let classes = &initial_info.original_classes;
+    text.char_indices()
+        .filter(|&(i, _)| classes[i] == FSI)
+        .map(|(i, _)| i..isolate_end(text, classes, i))
+        .collect()
+}
+
+/// Find the directional overrides (LRO, RLO) that are closed by a PDI instead of a PDF.
+///
+/// Such nesting is legal, since a PDI closes every embedding and override opened within its
+/// isolate (rule X6a), but it often means that the author forgot a PDF, or that text with an
+/// unterminated override was pasted into an isolate.  Returns the byte range of each such
+/// override, from its LRO or RLO to the end of the PDI that closes it, in the order of the PDIs.
+///
+/// This replays the changes of the directional status stack reported by
+/// [`BidiInfo::new_with_stack_trace()`](struct.BidiInfo.html#method.new_with_stack_trace), so
+/// overrides ignored because of an overflow are not reported.
+///
+/// <http://www.unicode.org/reports/tr9/#X6a>
+pub fn suspicious_control_nesting(text: &str) -> Vec<Range<usize>> {
+    let (bidi_info, events) = BidiInfo::new_with_stack_trace(text, None);
+
+    let mut spans = Vec::new();
+    // The byte index of the initiator of each entry pushed on the stack of the current paragraph.
+    let mut stack = Vec::new();
+    let mut para_end = 0;
+    for event in events {
+        if event.index >= para_end {
+            stack.clear();
+            para_end = bidi_info.paragraphs[bidi_info.paragraph_at(event.index)].range.end;
+        }
+        match event.kind {
+            StackEventKind::Push => stack.push(event.index),
+            StackEventKind::Pop => {
+                let start = stack.pop().expect("pop of a pushed entry");
+                let is_override = matches!(event.status, OverrideStatus::LTR | OverrideStatus::RTL);
+                if is_override && text[event.index..].starts_with(chars::PDI) {
+                    spans.push(start..event.index + chars::PDI.len_utf8());
+                }
+            }
+        }
+    }
+    spans
+}
+
+/// The byte index of the end of the isolate whose initiator is at `start`: after its matching PDI,
+/// or at the end of its paragraph.
+fn isolate_end(text: &str, classes: &[BidiClass], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, c) in text[start..].char_indices() {
+        let i = start + i;
+        match classes[i] {
+            RLI | LRI | FSI => depth += 1,
+            PDI => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + c.len_utf8();
+                }
+            }
+            B => return i,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Replace the embeddings of `text` by isolates where it doesn't change the result.
+///
+/// Isolates (LRI, RLI, FSI, PDI) are preferred over embeddings (LRE, RLE, PDF) in new text, since
+/// they don't affect the surrounding text.  This rewrites an LRE or RLE and its matching PDF into
+/// an LRI or RLI and a PDI, respectively, only if:
+///
+/// * the PDF is in the same paragraph, and there are no isolate formatting characters between
+///   them, and
+/// * the paragraph levels and the resolved levels of all characters other than the directional
+///   formatting characters stay the same, as checked by running the algorithm with automatic
+///   paragraph levels (P2, P3) on the rewritten text.
+///
+/// Overrides (LRO, RLO) have no isolate equivalent, and unmatched embeddings or PDFs are left
+/// untouched.  Since paragraphs are resolved independently, only the paragraph of a span is
+/// checked.  All the spans of a paragraph are first checked at once; if that changes the result,
+/// they are checked one at a time from the first to the last initiator, along with the
+/// replacements already made.  The replacements have the same length in UTF-8, so byte offsets
+/// into `text` remain valid.
+pub fn prefer_isolates(text: &str) -> Cow<'_, str> {
+    // Find the balanced LRE/RLE ... PDF spans, as (initiator, PDF) byte offsets.
+    let mut spans = Vec::new();
+    let mut stack: Vec<(usize, char)> = Vec::new();
+    for (i, c) in text.char_indices() {
+        match c {
+            chars::LRE | chars::RLE | chars::LRO | chars::RLO => stack.push((i, c)),
+            chars::PDF => {
+                if let Some((start, initiator)) = stack.pop() {
+                    if initiator == chars::LRE || initiator == chars::RLE {
+                        spans.push((start, i));
+                    }
+                }
+            }
+            chars::LRI | chars::RLI | chars::FSI | chars::PDI => stack.clear(),
+            _ if bidi_class(c) == B => stack.clear(),
+            _ => {}
+        }
+    }
+    if spans.is_empty() {
+        return text.into();
+    }
+    spans.sort();
+
+    let resolve = |text: &str| {
+        let bidi_info = BidiInfo::new(text, None);
+        let para_levels: Vec<Level> = bidi_info.paragraphs.iter().map(|p| p.level).collect();
+        let levels: Vec<Level> = text
+            .char_indices()
+            .filter(|&(_, c)| !chars::is_explicit_format_char(c))
+            .map(|(i, _)| bidi_info.levels[i])
+            .collect();
+        (para_levels, levels)
+    };
+    // Replace the span starting at `start` and ending with the PDF at `end` within `para`, a
+    // paragraph starting at `offset` in `text`.
+    let replace = |para: &mut String, offset: usize, (start, end): (usize, usize)| {
+        let isolate = if text[start..].starts_with(chars::LRE) {
+            chars::LRI
+        } else {
+            chars::RLI
+        };
+        let mut buf = [0; 4];
+        let (start, end) = (start - offset, end - offset);
+        para.replace_range(start..start + isolate.len_utf8(), isolate.encode_utf8(&mut buf));
+        para.replace_range(end..end + chars::PDF.len_utf8(), chars::PDI.encode_utf8(&mut buf));
+    };
+
+    let mut result = text.to_owned();
+    let mut changed = false;
+    for para in InitialInfo::new(text, None).paragraphs {
+        let range = para.range;
+        let para_spans: Vec<(usize, usize)> =
+            spans.iter().cloned().filter(|&(start, _)| range.contains(&start)).collect();
+        if para_spans.is_empty() {
+            continue;
+        }
+        let expected = resolve(&text[range.clone()]);
+
+        let mut all = text[range.clone()].to_owned();
+        for &span in &para_spans {
+            replace(&mut all, range.start, span);
+        }
+        let replaced = if resolve(&all) == expected {
+            all
+        } else {
+            let mut replaced = text[range.clone()].to_owned();
+            for &span in &para_spans {
+                let mut candidate = replaced.clone();
+                replace(&mut candidate, range.start, span);
+                if resolve(&candidate) == expected {
+                    replaced = candidate;
+                }
+            }
+            replaced
+        };
+        if replaced != text[range.clone()] {
+            result.replace_range(range, &replaced);
+            changed = true;
+        }
+    }
+    if changed {
+        result.into()
+    } else {
+        text.into()
+    }
+}
+
+/// Wrap `text` in isolate formatting characters, so that it can be embedded in another text
+/// without affecting the surrounding characters, nor being affected by them.
+///
+/// This is the bidi analog of escaping untrusted text before inserting it into a document.  The
+/// text is wrapped in an LRI or RLI for `Some(ResolvedDirection::Ltr)` or
+/// `Some(ResolvedDirection::Rtl)`, or in an FSI for `None` to let the text decide, and a PDI.
+/// Any directional formatting character of `text` that would not be balanced inside of the
+/// isolate is removed:
+///
+/// * an isolate initiator (LRI, RLI, FSI) without a matching PDI, and a PDI without a matching
+///   initiator (BD9),
+/// * an embedding or override initiator (LRE, RLE, LRO, RLO) without a matching PDF in the same
+///   isolate, and a PDF without a matching initiator,
+/// * any of the above that is still open at a paragraph separator, which terminates it.
+///
+/// Note that a paragraph separator within `text` still ends the isolate, since isolates can't span
+/// paragraphs.
+pub fn isolate(text: &str, dir: Option<ResolvedDirection>) -> String {
+    let initiator = match dir {
+        Some(ResolvedDirection::Ltr) => chars::LRI,
+        Some(ResolvedDirection::Rtl) => chars::RLI,
+        None => chars::FSI,
+    };
+
+    // The byte offsets of the unbalanced formatting characters, and the stack of open initiators
+    // as (byte offset, is an isolate initiator) pairs.
+    let mut removed = Vec::new();
+    let mut stack: Vec<(usize, bool)> = Vec::new();
+    for (i, c) in text.char_indices() {
+        match c {
+            chars::LRI | chars::RLI | chars::FSI => stack.push((i, true)),
+            chars::LRE | chars::RLE | chars::LRO | chars::RLO => stack.push((i, false)),
+            chars::PDI => {
+                if stack.iter().any(|&(_, is_isolate)| is_isolate) {
+                    // Like X6a, this closes the embeddings and overrides within the isolate.
+                    while let Some((j, is_isolate)) = stack.pop() {
+                        if is_isolate {
+                            break;
+                        }
+                        removed.push(j);
+                    }
+                } else {
+                    removed.push(i);
+                }
+            }
+            chars::PDF => match stack.last() {
+                Some(&(_, false)) => {
+                    stack.pop();
+                }
+                _ => removed.push(i),
+            },
+            _ if bidi_class(c) == B => removed.extend(stack.drain(..).map(|(j, _)| j)),
+            _ => {}
+        }
+    }
+    removed.extend(stack.into_iter().map(|(j, _)| j));
+    removed.sort_unstable();
+
+    let mut result = String::with_capacity(text.len() + 2 * chars::PDI.len_utf8());
+    result.push(initiator);
+    result.extend(
+        text.char_indices()
+            .filter(|&(i, _)| removed.binary_search(&i).is_err())
+            .map(|(_, c)| c),
+    );
+    result.push(chars::PDI);
+    result
+}
+
+/// The resolved levels of a fragment of text, e.g. pasted from the clipboard, when it is inserted
+/// into a document at a position whose embedding level is `surrounding_level`.
+///
+/// The fragment is resolved with `surrounding_level` as its paragraph level, so that its
+/// characters get the levels they would have at that position, as long as the characters
+/// around it are of the direction of `surrounding_level`.  Otherwise, the neutrals at the edges of
+/// the fragment may resolve differently (rules N1 and N2), so the whole document should be
+/// analyzed again.  Each paragraph separator in the fragment starts a new paragraph at the same
+/// level.  Like `BidiInfo::levels`, this has one entry per byte, and rule L1 is not applied.
+pub fn analyze_in_context(fragment: &str, surrounding_level: Level) -> Vec<Level> {
+    BidiInfo::new(fragment, Some(surrounding_level)).levels
+}
+
+/// Apply rule L2 to a line that was already split into runs, and return the indices of the runs
+/// in visual order.
+///
+/// `runs` holds the resolved level of each run, after applying rule L1, in logical order.  The
+/// second element of each pair is only carried along, so that e.g. styled runs can be reordered
+/// without slicing their text.  Adjacent runs may have the same level.
+///
+/// <http://www.unicode.org/reports/tr9/#L2>
+pub fn reorder_runs<T>(runs: &[(Level, T)]) -> Vec<usize> {
+    let levels: Vec<Level> = runs.iter().map(|run| run.0).collect();
+    let mut order: Vec<usize> = (0..runs.len()).collect();
+    apply_l2_reverse(&levels, &mut order);
+    order
+}
+
+/// Apply rule L2 to `items` in place, given the resolved level of each item after applying rule
+/// L1, so that the items end up in visual order.
+///
+/// This lets callers reorder their own data, like glyph advances or positions, without
+/// allocating a permutation.  `levels` must have the same length as `items`, and is left in
+/// logical order: each sequence of items at a level or higher keeps its place when the higher
+/// sequences nested within it are reversed, so its bounds can be found in `levels`.
+///
+/// <http://www.unicode.org/reports/tr9/#L2>
+pub fn apply_l2_reverse<T>(levels: &[Level], items: &mut [T]) {
+    assert_eq!(levels.len(), items.len());
+    if levels.is_empty() {
+        return;
+    }
+    let (min_level, mut max_level) = min_max_level(levels);
+
+    // Stop at the lowest *odd* level.
+    let min_level = min_level.new_lowest_ge_rtl().expect("Level error");
+
+    while max_level >= min_level {
+        // Reverse each sequence of consecutive items of max_level or higher.
+        let mut seq_start = 0;
+        while seq_start < levels.len() {
+            if levels[seq_start] < max_level {
+                seq_start += 1;
+                continue;
+            }
+            let mut seq_end = seq_start + 1;
+            while seq_end < levels.len() && levels[seq_end] >= max_level {
+                seq_end += 1;
+            }
+            items[seq_start..seq_end].reverse();
+            seq_start = seq_end;
+        }
+        max_level.lower(1).expect("Lowering embedding level below zero");
+    }
+}
+
+/// How mixed the directions of a paragraph are, as returned by
+/// [`BidiInfo::direction_stats()`](struct.BidiInfo.html#method.direction_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DirectionStats {
+    /// The number of characters with an even (LTR) level.
+    pub ltr_chars: usize,
+
+    /// The number of characters with an odd (RTL) level.
+    pub rtl_chars: usize,
+
+    /// The number of level runs, i.e. maximal sequences of characters with the same level.
+    pub run_count: usize,
+}
+
+/// The overall shape of a `BidiInfo`, as returned by
+/// [`BidiInfo::summary()`](struct.BidiInfo.html#method.summary).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BidiSummary {
+    /// The number of paragraphs.
+    pub paragraph_count: usize,
+
+    /// The number of paragraphs with an odd (RTL) paragraph level.
+    pub rtl_paragraph_count: usize,
+
+    /// The highest resolved level, before rule L1 is applied to any line, or level 0 if the text
+    /// is empty.
+    pub max_level: Level,
+
+    /// If any character has an odd (RTL) resolved level, like `BidiInfo::has_rtl()`.
+    pub has_rtl: bool,
+}
+
+/// Orientation hint for a run of vertical text.
+///
+/// Orientation is not part of the bidi algorithm, which never sets it; it is only carried along
+/// with each `Run` so that vertical layout engines don't have to recompute it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    /// No orientation was chosen.  This is the default.
+    Unspecified,
+    /// Glyphs are displayed upright, e.g. CJK ideographs.
+    Upright,
+    /// Glyphs are rotated 90° clockwise, e.g. Latin or Arabic text.
+    Sideways,
+}
+
+impl Default for Orientation {
+    fn default() -> Orientation {
+        Orientation::Unspecified
+    }
+}
+
+/// What to do with the characters removed by rule X9 when re-ordering a line.
+///
+/// See [`BidiInfo::reorder_line_with_removed()`][reorder].
+///
+/// [reorder]: struct.BidiInfo.html#method.reorder_line_with_removed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RemovedChars {
+    /// Keep them in the output, like `reorder_line()`.  This is the default.
+    Keep,
+    /// Leave them out of the output.
+    Drop,
+    /// Output the given character instead of each of them, e.g. U+FFFC OBJECT REPLACEMENT
+    /// CHARACTER.
+    Replace(char),
+}
+
+impl Default for RemovedChars {
+    fn default() -> RemovedChars {
+        RemovedChars::Keep
+    }
+}
+
+/// The level of the paragraph separator at the end of a line.
+///
+/// See [`BidiInfo::reordered_levels_with_separator()`][levels].
+///
+/// [levels]: struct.BidiInfo.html#method.reordered_levels_with_separator
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeparatorLevel {
+    /// The paragraph level, as specified by rule L1.  This is the default.
+    ParagraphLevel,
+    /// The level of the character before it (after rule L1), or the paragraph level if there is
+    /// none.
+    PrecedingRun,
+}
+
+impl Default for SeparatorLevel {
+    fn default() -> SeparatorLevel {
+        SeparatorLevel::ParagraphLevel
+    }
+}
+
+/// A level run within a line, with its resolved level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    /// The byte range of the run within the text.
+    pub range: LevelRun,
+
+    /// The resolved embedding level of the run, after applying rule L1.
+    pub level: Level,
+
+    /// Orientation hint for vertical text, `Orientation::Unspecified` by default.
+    pub orientation: Orientation,
+}
+
+impl Run {
+    /// A run without any orientation hint.
+    pub fn new(range: LevelRun, level: Level) -> Run {
+        Run {
+            range,
+            level,
+            orientation: Orientation::default(),
+        }
+    }
+
+    /// This run, with the given orientation hint.
+    pub fn with_orientation(self, orientation: Orientation) -> Run {
+        Run { orientation, ..self }
+    }
+
+    /// If the characters of this run should be displayed with mirrored glyphs where they have
+    /// one, i.e. if its level is odd (RTL).
+    ///
+    /// <http://www.unicode.org/reports/tr9/#L4>
+    pub fn needs_mirroring(&self) -> bool {
+        self.level.is_rtl()
+    }
+
+    /// The characters of this run to display with a mirrored glyph, along with their byte offsets
+    /// and the char to display instead, in logical order.
+    ///
+    /// `text` is the text that the range of the run refers to.  This is empty if the run doesn't
+    /// `needs_mirroring()`; otherwise it has each character with a `Bidi_Mirroring_Glyph` (see
+    /// [`bidi_mirroring_glyph()`](fn.bidi_mirroring_glyph.html)), like
+    /// [`BidiInfo::mirror_positions()`](struct.BidiInfo.html#method.mirror_positions) for a line.
+    pub fn mirror_substitutions(&self, text: &str) -> Vec<(usize, char)> {
+        if !self.needs_mirroring() {
+            return Vec::new();
+        }
+        text[self.range.clone()]
+            .char_indices()
+            .filter_map(|(i, c)| {
+                bidi_mirroring_glyph(c).map(|mirror| (self.range.start + i, mirror))
+            })
+            .collect()
+    }
+}
+
+/// Overall direction of a paragraph, based on its resolved embedding levels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    /// All characters have even (LTR) levels.
+    Ltr,
+    /// All characters have odd (RTL) levels.
+    Rtl,
+    /// Both even and odd levels are present.
+    Mixed,
+}
+
+/// Direction of a single character or span of text, which unlike `Direction` is never mixed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResolvedDirection {
+    /// Left-to-right, for an even level.
+    Ltr,
+    /// Right-to-left, for an odd level.
+    Rtl,
+}
+
+impl ResolvedDirection {
+    /// The direction of the text at `level`, from its parity.
+    fn of_level(level: Level) -> ResolvedDirection {
+        if level.is_rtl() {
+            ResolvedDirection::Rtl
+        } else {
+            ResolvedDirection::Ltr
+        }
+    }
+}
+
+/// The digits to display a number with, as returned by
+/// [`BidiInfo::digit_shaping()`](struct.BidiInfo.html#method.digit_shaping).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DigitShape {
+    /// European digits (U+0030..U+0039), for European numbers (`EN`).
+    European,
+    /// Arabic-Indic digits (U+0660..U+0669), for Arabic numbers (`AN`), including European
+    /// numbers after Arabic letters (rule W2).
+    ArabicIndic,
+}
+
+/// A visual edge of a line, as returned by
+/// [`BidiInfo::ellipsis_side()`](struct.BidiInfo.html#method.ellipsis_side).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VisualDirection {
+    /// The left edge.
+    Left,
+    /// The right edge.
+    Right,
+}
+
+/// A single paragraph of a `BidiInfo`.
+///
+/// Contains a reference to the `BidiInfo` and to one of its `paragraphs`, so that operations
+/// which need both can be called on the paragraph directly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Paragraph<'a, 'text: 'a> {
+    /// The analysis of the whole text.
+    pub info: &'a BidiInfo<'text>,
+    /// The paragraph, one of `info.paragraphs`.
+    pub para: &'a ParagraphInfo,
+}
+
+impl<'a, 'text> Paragraph<'a, 'text> {
+    /// The paragraph `para` of the analysis `info`.
+    pub fn new(info: &'a BidiInfo<'text>, para: &'a ParagraphInfo) -> Paragraph<'a, 'text> {
+        Paragraph { info, para }
+    }
+
+    /// Returns whether the paragraph is entirely LTR, entirely RTL, or mixed.
+    pub fn direction(&self) -> Direction {
+        let levels = &self.info.levels[self.para.range.clone()];
+        match (levels.iter().any(|l| l.is_ltr()), levels.iter().any(|l| l.is_rtl())) {
+            (true, true) => Direction::Mixed,
+            (false, true) => Direction::Rtl,
+            (_, false) => Direction::Ltr,
+        }
+    }
+
+    /// Returns the resolved level of the character at `pos`, a byte index relative to the start
+    /// of the paragraph.
+    ///
+    /// Panics if `pos` is not within the paragraph.
+    pub fn level_at(&self, pos: usize) -> Level {
+        assert!(pos < self.para.range.len(), "position outside of the paragraph");
+        let actual_position = self.para.range.start + pos;
+        self.info.levels[actual_position]
+    }
+
+    /// Re-order a line of this paragraph and return the line in display order.
+    ///
+    /// See [`BidiInfo::reorder_line()`](struct.BidiInfo.html#method.reorder_line).
+    pub fn reorder_line(&self, line: Range<usize>) -> Cow<'text, str> {
+        self.info.reorder_line(self.para, line)
+    }
+}
+
+/// Assign levels to characters removed by rule X9.
+///
+/// The levels assigned to these characters are not specified by the algorithm.  This function
+/// assigns each one the level of the previous character, to avoid breaking level runs.
+#[cfg_attr(feature = "flame_it", flame)]
+fn assign_levels_to_removed_chars(para_level: Level, classes: &[BidiClass], levels: &mut [Level]) {
+    for i in 0..levels.len() {
+        if prepare::removed_by_x9(classes[i]) {
+            levels[i] = if i > 0 { levels[i - 1] } else { para_level };
+        }
+    }
+}
+
+/// The lowest and highest of `levels`, which must not be empty.
+fn min_max_level(levels: &[Level]) -> (Level, Level) {
+    let mut min_level = levels[0];
+    let mut max_level = levels[0];
+    for &level in levels {
+        min_level = min(level, min_level);
+        max_level = max(level, max_level);
+    }
+    (min_level, max_level)
+}
+
+/// The resolved classes and levels of a text, borrowed from a `BidiInfo` or a `BidiInfoBuf`, with
+/// the methods that re-order its lines.
+#[derive(Clone, Copy)]
+struct Resolved<'a, 'text: 'a> {
+    text: &'text str,
+    original_classes: &'a [BidiClass],
+    levels: &'a [Level],
+}
+
+impl<'a, 'text> Resolved<'a, 'text> {
+    /// See `BidiInfo::reordered_levels()`.
+    fn reordered_levels(self, para: &ParagraphInfo, line: Range<usize>) -> Vec<Level> {
+        let (levels, _) = self.visual_runs(para, line);
+        levels
+    }
+
+    /// See `BidiInfo::reordered_levels_per_char()`.
+    fn reordered_levels_per_char(self, para: &ParagraphInfo, line: Range<usize>) -> Vec<Level> {
+        let levels = self.reordered_levels(para, line);
+        self.text.char_indices().map(|(i, _)| levels[i]).collect()
+    }
+
+    /// See `BidiInfo::reorder_line()`.
+    fn reorder_line(self, para: &ParagraphInfo, line: Range<usize>) -> Cow<'text, str> {
+        let (levels, runs) = self.line_visual_runs(para, line.clone());
+        self.reorder_visual_runs(line, &levels, &runs)
+    }
+
+    /// See `BidiInfo::visual_runs()`.
+    fn visual_runs(self, para: &ParagraphInfo, line: Range<usize>) -> (Vec<Level>, Vec<LevelRun>) {
+        let (line_levels, runs) = self.line_visual_runs(para, line.clone());
+        let mut levels = self.levels.to_vec();
+        levels[line].copy_from_slice(&line_levels);
+        (levels, runs)
+    }
+
+    /// The text of a line in display order, given its `line_visual_runs()`.
+    fn reorder_visual_runs(
+        self,
+        line: Range<usize>,
+        levels: &[Level],
+        runs: &[LevelRun],
+    ) -> Cow<'text, str> {
+        // If all isolating run sequences are LTR, no reordering is needed
+        if levels.iter().all(|level| level.is_ltr()) {
+            return self.text[line].into();
+        }
+
+        let mut result = String::with_capacity(line.len());
+        result.extend(self.visual_chars(line.start, levels, runs).map(|(_, c, _)| c));
+        result.into()
+    }
+
+    /// The characters of the line starting at `line_start` in display order, given its
+    /// `line_visual_runs()`, along with their byte offsets in the text and their levels.
+    fn visual_chars(
+        self,
+        line_start: usize,
+        levels: &'a [Level],
+        runs: &'a [LevelRun],
+    ) -> impl Iterator<Item = (usize, char, Level)> + 'a {
+        runs.iter().flat_map(move |run| {
+            let level = levels[run.start - line_start];
+            self.run_chars(run.clone(), level).map(move |(i, c)| (i, c, level))
+        })
+    }
+
+    /// The characters of a level run at `level` in display order, along with their byte offsets
+    /// in the text.
+    ///
+    /// The characters of an RTL run are reversed, but combining marks (`NSM`) are kept after
+    /// their base character (rule L3).
+    fn run_chars(self, run: LevelRun, level: Level) -> RunChars<'a> {
+        let text = &self.text[run.clone()];
+        RunChars {
+            classes: self.original_classes,
+            text,
+            start: run.start,
+            chars: text.char_indices(),
+            rtl: level.is_rtl(),
+            marks: None,
+            unattached_marks: false,
+        }
+    }
+
+    /// Like `visual_runs()`, but only return the levels of the line, indexed from `line.start`.
+    fn line_visual_runs(
+        self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+    ) -> (Vec<Level>, Vec<LevelRun>) {
+        let mut levels = Vec::with_capacity(line.len());
+        let mut runs = Vec::new();
+        self.visual_runs_into(para, line, &mut levels, &mut runs);
+        (levels, runs)
+    }
+
+    /// Like `line_visual_runs()`, but overwrite `levels` and `runs` instead of allocating new
+    /// vectors.
+    ///
+    /// An empty line has no runs.
+    fn visual_runs_into(
+        self,
+        para: &ParagraphInfo,
+        line: Range<usize>,
+        levels: &mut Vec<Level>,
+        runs: &mut Vec<LevelRun>,
+    ) {
+        self.line_levels_into(para, line.clone(), levels);
+
+        // Find consecutive level runs.
+        runs.clear();
+        if line.start == line.end {
+            return;
+        }
+        let mut start = line.start;
+        let mut run_level = levels[0];
+
+        for (i, &new_level) in levels.iter().enumerate().skip(1) {
+            if new_level != run_level {
+                // End of the previous run, start of a new one.
+                runs.push(start..line.start + i);
+                start = line.start + i;
+                run_level = new_level;
+            }
+        }
+        runs.push(start..line.end);
+
+        // Re-order the odd runs.
+        // <http://www.unicode.org/reports/tr9/#L2>
+        let run_levels: Vec<Level> =
+            runs.iter().map(|run| levels[run.start - line.start]).collect();
+        apply_l2_reverse(&run_levels, runs);
+    }
+
+    /// The levels of a line after applying rule L1, indexed from `line.start`.
+    fn line_levels(self, para: &ParagraphInfo, line: Range<usize>) -> Vec<Level> {
+        let mut levels = Vec::with_capacity(line.len());
+        self.line_levels_into(para, line, &mut levels);
+        levels
+    }
+
+    /// Like `line_levels()`, but overwrite `levels` instead of allocating a new vector.
+    fn line_levels_into(self, para: &ParagraphInfo, line: Range<usize>, levels: &mut Vec<Level>) {
+        assert!(line.start <= self.levels.len());
+        assert!(line.end <= self.levels.len());
+
+        levels.clear();
+        levels.extend_from_slice(&self.levels[line.clone()]);
+
+        // Reset some whitespace chars to paragraph level.
+        // <http://www.unicode.org/reports/tr9/#L1>
+        let line_str: &str = &self.text[line.clone()];
+        let mut reset_from: Option<usize> = Some(line.start);
+        let mut reset_to: Option<usize> = None;
+        for (i, c) in line_str.char_indices() {
+            // `original_classes` is indexed by byte offsets into the whole text, and `levels` by
+            // byte offsets into the line.
+            let i = line.start + i;
+            match self.original_classes[i] {
+                // Ignored by X9
+                RLE | LRE | RLO | LRO | PDF | BN => {}
+                // Segment separator, Paragraph separator
+                B | S => {
+                    assert_eq!(reset_to, None);
+                    reset_to = Some(i + c.len_utf8());
+                    if reset_from == None {
+                        reset_from = Some(i);
+                    }
+                }
+                // Whitespace, isolate formatting
+                WS | FSI | LRI | RLI | PDI => {
+                    if reset_from == None {
+                        reset_from = Some(i);
+                    }
+                }
+                _ => {
+                    reset_from = None;
+                }
+            }
+            if let (Some(from), Some(to)) = (reset_from, reset_to) {
+                for level in &mut levels[from - line.start..to - line.start] {
+                    *level = para.level;
+                }
+                reset_from = None;
+                reset_to = None;
+            }
+        }
+        if let Some(from) = reset_from {
+            for level in &mut levels[from - line.start..] {
+                *level = para.level;
+            }
+        }
+
+        // Characters removed by X9 got the level of the preceding character, which may be outside
+        // of the line, or may have been changed by L1.  Give them the level of the preceding
+        // character within the line (or of the following one, at the start of the line) instead,
+        // so that they don't split level runs.
+        let removed = |i: usize| prepare::removed_by_x9(self.original_classes[line.start + i]);
+        if let Some(first) = (0..line.len()).find(|&i| !removed(i)) {
+            for j in 0..first {
+                levels[j] = levels[first];
+            }
+            for j in first + 1..line.len() {
+                if removed(j) {
+                    levels[j] = levels[j - 1];
+                }
+            }
+        }
+    }
+}
+
+/// The characters of a level run in display order, along with their byte offsets in the text,
+/// as returned by `Resolved::run_chars()`.
+///
+/// An RTL run is read backwards.  When a combining mark (`NSM`) is reached, its base character is
+/// yielded first, followed by its marks in logical order (rule L3).
+struct RunChars<'a> {
+    classes: &'a [BidiClass],
+    // The text of the run, and its byte index within the whole text.
+    text: &'a str,
+    start: usize,
+    // The characters of the run that haven't been visited yet.
+    chars: CharIndices<'a>,
+    rtl: bool,
+    // The marks of the last base character of an RTL run, and the byte index of the first one.
+    marks: Option<(usize, CharIndices<'a>)>,
+    // Whether the remaining characters are marks without a base character within the run.
+    unattached_marks: bool,
+}
+
+impl<'a> Iterator for RunChars<'a> {
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<(usize, char)> {
+        let start = self.start;
+        if !self.rtl {
+            return self.chars.next().map(|(i, c)| (start + i, c));
+        }
+        if let Some((marks_start, ref mut marks)) = self.marks {
+            if let Some((i, c)) = marks.next() {
+                return Some((marks_start + i, c));
+            }
+        }
+        self.marks = None;
+
+        let (i, c) = self.chars.next_back()?;
+        if self.unattached_marks || self.classes[start + i] != NSM {
+            return Some((start + i, c));
+        }
+
+        // Find the base character of this mark and the marks before it.
+        let classes = self.classes;
+        let mut chars = self.chars.clone();
+        let base = chars
+            .by_ref()
+            .rev()
+            .find(|&(j, _)| classes[start + j] != NSM);
+        match base {
+            Some((j, base)) => {
+                let marks_start = j + base.len_utf8();
+                let marks_end = i + c.len_utf8();
+                self.chars = chars;
+                self.marks = Some((
+                    start + marks_start,
+                    self.text[marks_start..marks_end].char_indices(),
+                ));
+                Some((start + j, base))
+            }
+            None => {
+                // The marks at the start of the run are simply reversed.
+                self.unattached_marks = true;
+                Some((start + i, c))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_initial_text_info() {
+        let text = "a1";
+        assert_eq!(
+            InitialInfo::new(text, None),
+            InitialInfo {
+                text,
+                default_para_level: None,
+                original_classes: vec![L, EN],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..2,
+                        level: LTR_LEVEL,
+                    },
+                ],
+            }
+        );
+
+        let text = "غ א";
+        assert_eq!(
+            InitialInfo::new(text, None),
+            InitialInfo {
+                text,
+                default_para_level: None,
+                original_classes: vec![AL, AL, WS, R, R],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..5,
+                        level: RTL_LEVEL,
+                    },
+                ],
+            }
+        );
+
+        let text = "a\u{2029}b";
+        assert_eq!(
+            InitialInfo::new(text, None),
+            InitialInfo {
+                text,
+                default_para_level: None,
+                original_classes: vec![L, B, B, B, L],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..4,
+                        level: LTR_LEVEL,
+                    },
+                    ParagraphInfo {
+                        range: 4..5,
+                        level: LTR_LEVEL,
+                    },
+                ],
+            }
+        );
+
+        let text = format!("{}א{}a", chars::FSI, chars::PDI);
+        assert_eq!(
+            InitialInfo::new(&text, None),
+            InitialInfo {
+                text: &text,
+                default_para_level: None,
+                original_classes: vec![RLI, RLI, RLI, R, R, PDI, PDI, PDI, L],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..9,
+                        level: LTR_LEVEL,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_process_text() {
+        let text = "abc123";
+        assert_eq!(
+            BidiInfo::new(text, Some(LTR_LEVEL)),
+            BidiInfo {
+                text,
+                default_para_level: Some(LTR_LEVEL),
+                levels: Level::vec(&[0, 0, 0, 0, 0, 0]),
+                original_classes: vec![L, L, L, EN, EN, EN],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..6,
+                        level: LTR_LEVEL,
+                    },
+                ],
+            }
+        );
+
+        let text = "abc אבג";
+        assert_eq!(
+            BidiInfo::new(text, Some(LTR_LEVEL)),
+            BidiInfo {
+                text,
+                default_para_level: Some(LTR_LEVEL),
+                levels: Level::vec(&[0, 0, 0, 0, 1, 1, 1, 1, 1, 1]),
+                original_classes: vec![L, L, L, WS, R, R, R, R, R, R],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..10,
+                        level: LTR_LEVEL,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            BidiInfo::new(text, Some(RTL_LEVEL)),
+            BidiInfo {
+                text,
+                default_para_level: Some(RTL_LEVEL),
+                levels: Level::vec(&[2, 2, 2, 1, 1, 1, 1, 1, 1, 1]),
+                original_classes: vec![L, L, L, WS, R, R, R, R, R, R],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..10,
+                        level: RTL_LEVEL,
+                    },
+                ],
+            }
+        );
+
+        let text = "אבג abc";
+        assert_eq!(
+            BidiInfo::new(text, Some(LTR_LEVEL)),
+            BidiInfo {
+                text,
+                default_para_level: Some(LTR_LEVEL),
+                levels: Level::vec(&[1, 1, 1, 1, 1, 1, 0, 0, 0, 0]),
+                original_classes: vec![R, R, R, R, R, R, WS, L, L, L],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..10,
+                        level: LTR_LEVEL,
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            BidiInfo::new(text, None),
+            BidiInfo {
+                text,
+                default_para_level: None,
+                levels: Level::vec(&[1, 1, 1, 1, 1, 1, 1, 2, 2, 2]),
+                original_classes: vec![R, R, R, R, R, R, WS, L, L, L],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..10,
+                        level: RTL_LEVEL,
+                    },
+                ],
+            }
+        );
+
+        let text = "غ2ظ א2ג";
+        assert_eq!(
+            BidiInfo::new(text, Some(LTR_LEVEL)),
+            BidiInfo {
+                text,
+                default_para_level: Some(LTR_LEVEL),
+                levels: Level::vec(&[1, 1, 2, 1, 1, 1, 1, 1, 2, 1, 1]),
+                original_classes: vec![AL, AL, EN, AL, AL, WS, R, R, EN, R, R],
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..11,
+                        level: LTR_LEVEL,
+                    },
+                ],
+            }
+        );
+
+        let text = "a א.\nג";
+        assert_eq!(
+            BidiInfo::new(text, None),
+            BidiInfo {
+                text,
+                default_para_level: None,
+                original_classes: vec![L, WS, R, R, CS, B, R, R],
+                levels: Level::vec(&[0, 0, 1, 1, 0, 0, 1, 1]),
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..6,
+                        level: LTR_LEVEL,
+                    },
+                    ParagraphInfo {
+                        range: 6..8,
+                        level: RTL_LEVEL,
+                    },
+                ],
+            }
+        );
+
+        /// BidiTest:69635 (AL ET EN)
+        let bidi_info = BidiInfo::new("\u{060B}\u{20CF}\u{06F9}", None);
+        assert_eq!(bidi_info.original_classes, vec![AL, AL, ET, ET, ET, EN, EN]);
+    }
+
+    #[test]
+    fn test_new_from() {
+        let text = "abc אבג";
+        let expected = BidiInfo::new(text, None);
+
+        let owned = String::from(text);
+        assert_eq!(BidiInfo::new_from(&owned, None), expected);
+
+        let borrowed: Cow<'_, str> = Cow::Borrowed(text);
+        assert_eq!(BidiInfo::new_from(&borrowed, None), expected);
+        let cow_owned: Cow<'_, str> = Cow::Owned(owned.clone());
+        assert_eq!(BidiInfo::new_from(&cow_owned, Some(RTL_LEVEL)).paragraphs[0].level, RTL_LEVEL);
+
+        assert_eq!(BidiInfo::new_from(text, None), expected);
+    }
+
+    #[test]
+    fn test_new_with_data_source() {
+        struct AllRtl;
+        impl BidiDataSource for AllRtl {
+            fn bidi_class(&self, _: char) -> BidiClass {
+                R
+            }
+        }
+
+        let text = "abc אבג";
+        assert_eq!(
+            BidiInfo::new_with_data_source(text, None, &HardcodedBidiData),
+            BidiInfo::new(text, None)
+        );
+        let bidi_info = BidiInfo::new_with_data_source(text, None, &AllRtl);
+        assert_eq!(bidi_info.paragraphs[0].level, RTL_LEVEL);
+        assert!(bidi_info.original_classes.iter().all(|&class| class == R));
+    }
+
+    #[test]
+    fn test_new_with_class_overrides() {
+        // An object replacement character (ON) given the class R
+        let text = "abc \u{FFFC} def";
+        let bidi_info = BidiInfo::new_with_class_overrides(text, None, &[(4..7, R)]).unwrap();
+        assert_eq!(bidi_info.original_classes[4..7], [R, R, R]);
+        assert_eq!(bidi_info.levels[4..7], Level::vec(&[1, 1, 1])[..]);
+        assert_eq!(
+            BidiInfo::new_with_class_overrides(text, None, &[]).unwrap(),
+            BidiInfo::new(text, None)
+        );
+
+        // The last overlapping range wins.
+        let bidi_info =
+            BidiInfo::new_with_class_overrides("abc", None, &[(0..3, R), (1..2, AL)]).unwrap();
+        assert_eq!(bidi_info.original_classes, vec![R, AL, R]);
+        let overrides = [(4..6, R), (0..2, AL), (1..5, EN), (3..4, L)];
+        let bidi_info = BidiInfo::new_with_class_overrides("abcdefg", None, &overrides).unwrap();
+        assert_eq!(bidi_info.original_classes, vec![AL, EN, EN, L, EN, R, L]);
+
+        let reversed = Range { start: 5, end: 4 };
+        assert_eq!(
+            BidiInfo::new_with_class_overrides(text, None, &[(reversed.clone(), R)]),
+            Err(BidiError::InvalidOverrideRange { range: reversed })
+        );
+        assert_eq!(
+            BidiInfo::new_with_class_overrides(text, None, &[(0..12, R)]),
+            Err(BidiError::InvalidOverrideRange { range: 0..12 })
+        );
+        assert_eq!(
+            BidiInfo::new_with_class_overrides(text, None, &[(5..7, R)]),
+            Err(BidiError::NonCharBoundary { index: 5 })
+        );
+    }
+
+    #[test]
+    fn test_new_with_object_directions() {
+        // An object between two Hebrew words, in an LTR paragraph.
+        let text = "אב\u{FFFC}גד";
+        let levels = |direction| {
+            let objects = [(4, direction)];
+            BidiInfo::new_with_object_directions(text, Some(LTR_LEVEL), &objects)
+                .unwrap()
+                .levels
+        };
+        assert_eq!(levels(None), BidiInfo::new(text, Some(LTR_LEVEL)).levels);
+        assert_eq!(levels(None)[4..7], Level::vec(&[1, 1, 1])[..]);
+        assert_eq!(levels(Some(ResolvedDirection::Rtl))[4..7], Level::vec(&[1, 1, 1])[..]);
+        assert_eq!(levels(Some(ResolvedDirection::Ltr))[4..7], Level::vec(&[0, 0, 0])[..]);
+
+        let objects = [(4, Some(ResolvedDirection::Ltr))];
+        let bidi_info = BidiInfo::new_with_object_directions(text, None, &objects).unwrap();
+        assert_eq!(bidi_info.original_classes[4..7], [L, L, L]);
+        assert_eq!(
+            BidiInfo::new_with_object_directions(text, None, &[]).unwrap(),
+            BidiInfo::new(text, None)
+        );
+
+        for &index in &[0, 5, 11, 12] {
+            assert_eq!(
+                BidiInfo::new_with_object_directions(text, None, &[(index, None)]),
+                Err(BidiError::NotObjectReplacementChar { index })
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_per_line_auto() {
+        fn line_levels(text: &str) -> Vec<Level> {
+            BidiInfo::new_per_line_auto(text)
+                .paragraphs
+                .iter()
+                .map(|para| para.level)
+                .collect()
+        }
+
+        assert_eq!(line_levels("abc\nאבג"), vec![LTR_LEVEL, RTL_LEVEL]);
+        assert_eq!(line_levels("אבג\nabc\n"), vec![RTL_LEVEL, LTR_LEVEL]);
+
+        // A line separator ends a line, but not a paragraph.
+        let text = "abc\u{2028}אבג";
+        assert_eq!(line_levels(text), vec![LTR_LEVEL, RTL_LEVEL]);
+        assert_eq!(BidiInfo::new(text, None).paragraphs.len(), 1);
+        assert_eq!(
+            BidiInfo::new_per_line_auto(text).levels,
+            Level::vec(&[0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1])
+        );
+    }
+
+    #[test]
+    fn test_paragraph_base_info() {
+        let bidi_info = BidiInfo::new("אבג abc\nabc", None);
+        assert_eq!(
+            bidi_info.paragraph_base_info(&bidi_info.paragraphs[0]),
+            (None, RTL_LEVEL)
+        );
+        assert_eq!(
+            bidi_info.paragraph_base_info(&bidi_info.paragraphs[1]),
+            (None, LTR_LEVEL)
+        );
+
+        let bidi_info = BidiInfo::new("אבג abc", Some(LTR_LEVEL));
+        assert_eq!(
+            bidi_info.paragraph_base_info(&bidi_info.paragraphs[0]),
+            (Some(LTR_LEVEL), LTR_LEVEL)
+        );
+
+        let bidi_info = BidiInfo::new_with_mode("abc", ParaLevelMode::Rtl);
+        assert_eq!(bidi_info.default_para_level, Some(RTL_LEVEL));
+        let bidi_info = BidiInfo::new_with_para_levels("abc", &[RTL_LEVEL]).unwrap();
+        assert_eq!(
+            bidi_info.paragraph_base_info(&bidi_info.paragraphs[0]),
+            (None, RTL_LEVEL)
+        );
+    }
+
+    #[test]
+    fn test_para_level_from_hint() {
+        assert_eq!(para_level_from_hint("ltr"), Ok(ParaLevelMode::Ltr));
+        assert_eq!(para_level_from_hint("rtl"), Ok(ParaLevelMode::Rtl));
+        assert_eq!(para_level_from_hint("auto"), Ok(ParaLevelMode::Auto));
+        assert_eq!(para_level_from_hint("RTL"), Ok(ParaLevelMode::Rtl));
+        assert_eq!("Auto".parse::<ParaLevelMode>(), Ok(ParaLevelMode::Auto));
+        assert_eq!(
+            para_level_from_hint("rtl "),
+            Err(ParaLevelModeParseError("rtl ".to_owned()))
+        );
+        assert_eq!(para_level_from_hint(""), Err(ParaLevelModeParseError("".to_owned())));
+    }
+
+    #[test]
+    fn test_new_with_mode() {
+        fn para_levels(text: &str, mode: ParaLevelMode) -> (Vec<Level>, Vec<Level>) {
+            let bidi_info = BidiInfo::new_with_mode(text, mode);
+            (
+                bidi_info.paragraphs.iter().map(|para| para.level).collect(),
+                bidi_info.levels,
+            )
+        }
+
+        // A digits-only paragraph has no strong character.
+        let text = "123";
+        assert_eq!(
+            para_levels(text, ParaLevelMode::Auto),
+            (vec![LTR_LEVEL], Level::vec(&[0, 0, 0]))
+        );
+        assert_eq!(
+            para_levels(text, ParaLevelMode::Ltr),
+            (vec![LTR_LEVEL], Level::vec(&[0, 0, 0]))
+        );
+        assert_eq!(
+            para_levels(text, ParaLevelMode::Rtl),
+            (vec![RTL_LEVEL], Level::vec(&[2, 2, 2]))
+        );
+        assert_eq!(
+            para_levels(text, ParaLevelMode::Fallback(RTL_LEVEL)),
+            (vec![RTL_LEVEL], Level::vec(&[2, 2, 2]))
+        );
+        assert_eq!(
+            para_levels(text, ParaLevelMode::Fallback(LTR_LEVEL)),
+            para_levels(text, ParaLevelMode::Auto)
+        );
+
+        // The fallback only applies to paragraphs without a strong character.
+        let text = "abc\n123\nאבג";
+        assert_eq!(
+            para_levels(text, ParaLevelMode::Fallback(RTL_LEVEL)).0,
+            vec![LTR_LEVEL, RTL_LEVEL, RTL_LEVEL]
+        );
+        assert_eq!(
+            para_levels(text, ParaLevelMode::Auto).0,
+            vec![LTR_LEVEL, LTR_LEVEL, RTL_LEVEL]
+        );
+
+        // `Auto`, `Ltr` and `Rtl` match their `Option<Level>` equivalents.
+        assert_eq!(
+            BidiInfo::new_with_mode(text, ParaLevelMode::Auto),
+            BidiInfo::new(text, None)
+        );
+        assert_eq!(
+            BidiInfo::new_with_mode(text, ParaLevelMode::Ltr),
+            BidiInfo::new(text, Some(LTR_LEVEL))
+        );
+        assert_eq!(
+            BidiInfo::new_with_mode(text, ParaLevelMode::Rtl),
+            BidiInfo::new(text, Some(RTL_LEVEL))
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let text = "abc אבג";
+        assert_eq!(BidiInfoBuilder::new().build(text), BidiInfo::new(text, None));
+        assert_eq!(
+            BidiInfoBuilder::new()
+                .para_level_mode(ParaLevelMode::Rtl)
+                .build(text),
+            BidiInfo::new(text, Some(RTL_LEVEL))
+        );
+    }
+
+    #[test]
+    fn test_neutral_fallback() {
+        // The neutrals at the start and end of the isolating run sequences are between the
+        // paragraph direction (sos, eos) and a strong character of the opposite direction.
+        let text = "!? אבג ?!";
+        let build = |direction| {
+            BidiInfoBuilder::new()
+                .para_level_mode(ParaLevelMode::Ltr)
+                .neutral_fallback(direction)
+                .build(text)
+        };
+        let default = build(None);
+        assert_eq!(default, BidiInfo::new(text, Some(LTR_LEVEL)));
+        assert_eq!(default.levels[..3], Level::vec(&[0, 0, 0])[..]);
+        assert_eq!(default.levels[9..], Level::vec(&[0, 0, 0])[..]);
+
+        let rtl = build(Some(ResolvedDirection::Rtl));
+        assert_eq!(rtl.levels, Level::vec(&[1; 12]));
+
+        // Neutrals between strong characters of the same direction are not affected (rule N1).
+        let text = "a ! b";
+        let bidi_info = BidiInfoBuilder::new()
+            .neutral_fallback(Some(ResolvedDirection::Rtl))
+            .build(text);
+        assert_eq!(bidi_info.levels, Level::vec(&[0; 5]));
+    }
+
+    #[test]
+    fn test_error_on_overflow() {
+        // A second paragraph, with isolates nested one level too deep: 62 LRIs fit on the stack.
+        let mut text = String::from("a\n");
+        for _ in 0..63 {
+            text.push(chars::LRI);
+        }
+        text.push('b');
+        let overflow = 2 + 62 * chars::LRI.len_utf8();
+
+        let bidi_info = BidiInfoBuilder::new().build(&text);
+        assert_eq!(BidiInfoBuilder::new().try_build(&text), Ok(bidi_info.clone()));
+        assert_eq!(
+            BidiInfoBuilder::new().error_on_overflow(false).try_build(&text),
+            Ok(bidi_info)
+        );
+        assert_eq!(
+            BidiInfoBuilder::new().error_on_overflow(true).try_build(&text),
+            Err(BidiError::IsolateOverflow { index: overflow })
+        );
+
+        // Without the last LRI, the text fits.
+        text.remove(overflow);
+        assert_eq!(
+            BidiInfoBuilder::new().error_on_overflow(true).try_build(&text),
+            Ok(BidiInfo::new(&text, None))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "use try_build()")]
+    fn test_build_with_error_on_overflow() {
+        BidiInfoBuilder::new().error_on_overflow(true).build("abc");
+    }
+
+    #[test]
+    fn test_with_pua_class() {
+        let text = "\u{E000}\u{E001} ab \u{F0000}\u{10FFFD}";
+
+        let bidi_info = BidiInfoBuilder::new().build(text);
+        assert_eq!(bidi_info.original_classes[0], L);
+        assert_eq!(bidi_info.paragraphs[0].level, LTR_LEVEL);
+        assert!(!bidi_info.has_rtl());
+
+        let bidi_info = BidiInfoBuilder::new().with_pua_class(R).build(text);
+        assert_eq!(bidi_info.original_classes[0], R);
+        assert_eq!(bidi_info.original_classes[text.len() - 1], R);
+        assert_eq!(bidi_info.paragraphs[0].level, RTL_LEVEL);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(
+            bidi_info.reorder_line(para, para.range.clone()),
+            "\u{10FFFD}\u{F0000} ab \u{E001}\u{E000}"
+        );
+
+        // Other code points are unaffected.
+        assert_eq!(bidi_info.original_classes[7], L);
+    }
+
+    #[test]
+    fn test_new_with_para_levels() {
+        let text = "abc\nאבג\nabc";
+
+        let bidi_info = BidiInfo::new_with_para_levels(text, &[RTL_LEVEL, LTR_LEVEL, LTR_LEVEL])
+            .unwrap();
+        assert_eq!(
+            bidi_info.paragraphs.iter().map(|para| para.level).collect::<Vec<_>>(),
+            vec![RTL_LEVEL, LTR_LEVEL, LTR_LEVEL]
+        );
+        // The forced RTL base raises the Latin letters of the first paragraph to level 2.
+        assert_eq!(bidi_info.levels[..4], Level::vec(&[2, 2, 2, 1])[..]);
+        assert_eq!(bidi_info.levels[4..11], Level::vec(&[1, 1, 1, 1, 1, 1, 0])[..]);
+        assert_eq!(bidi_info.levels[11..], Level::vec(&[0, 0, 0])[..]);
+
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.reorder_line(para, para.range.clone()), "\nabc");
+
+        assert_eq!(
+            BidiInfo::new_with_para_levels(text, &[RTL_LEVEL]),
+            Err(BidiError::MismatchedLength {
+                expected: 3,
+                actual: 1,
+            })
+        );
+        assert_eq!(
+            BidiInfo::new_with_para_levels("", &[]).unwrap(),
+            BidiInfo::new("", None)
+        );
+        let level = Level::new(Level::max_implicit_depth()).unwrap();
+        assert_eq!(
+            BidiInfo::new_with_para_levels("abc", &[level]),
+            Err(BidiError::LevelTooDeep { level })
+        );
+    }
+
+    #[test]
+    fn test_paragraphs_at_end_of_text() {
+        // Without a trailing paragraph separator, the last paragraph ends at the end of the text.
+        let bidi_info = BidiInfo::new("אבג", None);
+        assert_eq!(
+            bidi_info.paragraphs,
+            vec![ParagraphInfo { range: 0..6, level: RTL_LEVEL }]
+        );
+        assert_eq!(bidi_info.levels, Level::vec(&[1, 1, 1, 1, 1, 1]));
+
+        let bidi_info = BidiInfo::new("abc\nאבג", None);
+        assert_eq!(
+            bidi_info.paragraphs,
+            vec![
+                ParagraphInfo { range: 0..4, level: LTR_LEVEL },
+                ParagraphInfo { range: 4..10, level: RTL_LEVEL },
+            ]
+        );
+
+        // A trailing paragraph separator doesn't start an empty paragraph.
+        let bidi_info = BidiInfo::new("אבג\n", None);
+        assert_eq!(
+            bidi_info.paragraphs,
+            vec![ParagraphInfo { range: 0..7, level: RTL_LEVEL }]
+        );
+
+        // An empty text has no paragraphs.
+        assert_eq!(BidiInfo::new("", None).paragraphs, vec![]);
+        assert_eq!(BidiInfo::new("", Some(RTL_LEVEL)).paragraphs, vec![]);
+    }
+
+    #[test]
+    fn test_new_with_diagnostics() {
+        let text = "abc אבג";
+        let (bidi_info, diagnostics) = BidiInfo::new_with_diagnostics(text, None);
+        assert_eq!(bidi_info, BidiInfo::new(text, None));
+        assert_eq!(diagnostics, Diagnostics::default());
+
+        // Two isolates fit on the stack; with an LTR paragraph, their levels are 2 and 4.
+        let text = "\u{2066}\u{2066}a\u{2069}\u{2069}";
+        let (_, diagnostics) = BidiInfo::new_with_diagnostics(text, None);
+        assert_eq!(
+            diagnostics,
+            Diagnostics {
+                overflow_isolate_count: 0,
+                overflow_embedding_count: 0,
+                valid_isolate_count: 2,
+                first_overflow_index: None,
+            }
+        );
+
+        // Isolates nested deeper than max_depth overflow.  Only 62 LRIs are valid, since each one
+        // raises the level by two, up to 124.
+        let mut text = String::new();
+        for _ in 0..65 {
+            text.push(chars::LRI);
+        }
+        text.push('a');
+        for _ in 0..2 {
+            text.push(chars::LRE);
+        }
+        let (bidi_info, diagnostics) = BidiInfo::new_with_diagnostics(&text, None);
+        assert_eq!(
+            diagnostics,
+            Diagnostics {
+                overflow_isolate_count: 3,
+                overflow_embedding_count: 0,
+                valid_isolate_count: 62,
+                first_overflow_index: Some(62 * chars::LRI.len_utf8()),
+            }
+        );
+        assert_eq!(bidi_info.levels[text.find('a').unwrap()].number(), 124);
+
+        let mut text = String::new();
+        for _ in 0..64 {
+            text.push(chars::LRE);
+        }
+        let (_, diagnostics) = BidiInfo::new_with_diagnostics(&text, None);
+        assert_eq!(diagnostics.overflow_embedding_count, 2);
+        assert_eq!(diagnostics.overflow_isolate_count, 0);
+        assert_eq!(diagnostics.first_overflow_index, Some(62 * chars::LRE.len_utf8()));
+    }
+
+    #[test]
+    fn test_new_with_stack_trace() {
+        fn event(index: usize, kind: StackEventKind, level: u8, status: OverrideStatus)
+            -> StackEvent {
+            StackEvent { index, kind, level: Level::new(level).unwrap(), status }
+        }
+        use OverrideStatus::*;
+        use StackEventKind::*;
+
+        // a RLE b LRO c PDF RLI d PDI PDF B RLI LRE x PDI
+        let text = concat!(
+            "a\u{202B}b\u{202D}c\u{202C}\u{2067}d\u{2069}\u{202C}\n",
+            "\u{2067}\u{202A}x\u{2069}",
+        );
+        let (bidi_info, events) = BidiInfo::new_with_stack_trace(text, None);
+        assert_eq!(bidi_info, BidiInfo::new(text, None));
+        assert_eq!(
+            events,
+            vec![
+                event(1, Push, 1, Neutral),
+                event(5, Push, 2, LTR),
+                event(9, Pop, 2, LTR),
+                event(12, Push, 3, Isolate),
+                event(16, Pop, 3, Isolate),
+                event(19, Pop, 1, Neutral),
+                // The second paragraph: the PDI also pops the embedding within the isolate.
+                event(23, Push, 1, Isolate),
+                event(26, Push, 2, Neutral),
+                event(30, Pop, 2, Neutral),
+                event(30, Pop, 1, Isolate),
+            ]
+        );
+
+        // A PDF without a matching embedding doesn't pop anything.
+        let (_, events) = BidiInfo::new_with_stack_trace("a\u{202C}", None);
+        assert_eq!(events, vec![]);
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn test_new_with_trace() {
+        // AL EN: W2 changes the EN to AN.
+        let text = "\u{0627}1";
+        let (bidi_info, events) = BidiInfo::new_with_trace(text, None);
+        assert_eq!(bidi_info, BidiInfo::new(text, None));
+        assert_eq!(
+            events,
+            vec![
+                RuleEvent {
+                    rule: "W2",
+                    index: 2,
+                    from: EN,
+                    to: AN,
+                },
+                RuleEvent {
+                    rule: "W3",
+                    index: 0,
+                    from: AL,
+                    to: R,
+                },
+            ]
+        );
+
+        // Indices are relative to the whole text.
+        let text = "a\n1 \u{05D0}";
+        let (_, events) = BidiInfo::new_with_trace(text, Some(RTL_LEVEL));
+        assert_eq!(
+            events,
+            vec![
+                RuleEvent {
+                    rule: "N2",
+                    index: 1,
+                    from: B,
+                    to: R,
+                },
+                RuleEvent {
+                    rule: "N1",
+                    index: 3,
+                    from: WS,
+                    to: R,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nsm_at_isolate_start() {
+        // The NSM starts the isolating run sequence of the RLI, whose sos is R, so it resolves to
+        // level 1.  Outside of the isolate it would follow the L.
+        let text = "a\u{2067}\u{0301}\u{2069}";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.levels[4..6], Level::vec(&[1, 1])[..]);
+
+        let bidi_info = BidiInfo::new("a\u{0301}", None);
+        assert_eq!(bidi_info.levels, Level::vec(&[0, 0, 0]));
+    }
+
+    #[test]
+    fn test_bidi_info_buf() {
+        let text = "abc אבג\nדהו def";
+        let buf = BidiInfoBuf::new(text.to_owned(), None);
+        assert_eq!(buf.bidi_info(), BidiInfo::new(text, None));
+        assert_eq!(BidiInfoBuf::from(BidiInfo::new(text, None)), buf);
+        assert!(buf.has_rtl());
+
+        let bidi_info = BidiInfo::new(text, None);
+        for (para, line) in bidi_info.paragraphs.iter().zip(&[0..11, 11..21]) {
+            assert_eq!(
+                buf.reorder_line(para, line.clone()),
+                bidi_info.reorder_line(para, line.clone())
+            );
+            assert_eq!(
+                buf.reordered_levels(para, line.clone()),
+                bidi_info.reordered_levels(para, line.clone())
+            );
+            assert_eq!(
+                buf.reordered_levels_per_char(para, line.clone()),
+                bidi_info.reordered_levels_per_char(para, line.clone())
+            );
+            assert_eq!(
+                buf.visual_runs(para, line.clone()),
+                bidi_info.visual_runs(para, line.clone())
+            );
+        }
+
+        let buf = BidiInfoBuf::new("abc".to_owned(), Some(RTL_LEVEL));
+        assert_eq!(buf.default_para_level, Some(RTL_LEVEL));
+        assert_eq!(buf.bidi_info(), BidiInfo::new("abc", Some(RTL_LEVEL)));
+    }
+
+    #[test]
+    fn test_unmatched_isolate_initiator() {
+        // An RLI without a matching PDI isolates the rest of the paragraph (BD9).  The RLI itself
+        // gets the paragraph level, and the text after it is resolved at the isolate's level 1.
+        let text = "abc\u{2067}def";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.paragraphs[0].level, LTR_LEVEL);
+        assert_eq!(bidi_info.levels, Level::vec(&[0, 0, 0, 0, 0, 0, 2, 2, 2]));
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.reorder_line(para, para.range.clone()), text);
+
+        let text = "abc\u{2067}אב 12";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.levels[3..6], Level::vec(&[0, 0, 0])[..]);
+        assert_eq!(bidi_info.levels[6..], Level::vec(&[1, 1, 1, 1, 1, 2, 2])[..]);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.reorder_line(para, para.range.clone()), "abc\u{2067}12 בא");
+
+        // The isolate ends with its paragraph.
+        let text = "abc\u{2067}def\nghi";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.levels[6..9], Level::vec(&[2, 2, 2])[..]);
+        assert_eq!(bidi_info.levels[10..], Level::vec(&[0, 0, 0])[..]);
+    }
+
+    #[test]
+    fn test_removed_chars_are_skipped() {
+        // U+00AD SOFT HYPHEN and U+200B ZERO WIDTH SPACE are both BN.
+
+        // Bidi_Class: EN BN ES BN EN (W4)
+        let bidi_info = BidiInfo::new("1\u{00AD}+\u{00AD}2", Some(RTL_LEVEL));
+        assert_eq!(bidi_info.original_classes, vec![EN, BN, BN, ES, BN, BN, EN]);
+        assert_eq!(bidi_info.levels, Level::vec(&[2, 2, 2, 2, 2, 2, 2]));
+
+        // Bidi_Class: EN BN CS BN EN (W4)
+        let bidi_info = BidiInfo::new("1\u{200B},\u{200B}2", Some(RTL_LEVEL));
+        assert_eq!(
+            bidi_info.levels,
+            Level::vec(&[2, 2, 2, 2, 2, 2, 2, 2, 2])
+        );
+
+        // Bidi_Class: ET BN EN (W5)
+        let bidi_info = BidiInfo::new("$\u{00AD}1", Some(RTL_LEVEL));
+        assert_eq!(bidi_info.levels, Level::vec(&[2, 2, 2, 2]));
+
+        // Bidi_Class: EN BN ET (W5)
+        let bidi_info = BidiInfo::new("1\u{00AD}$", Some(RTL_LEVEL));
+        assert_eq!(bidi_info.levels, Level::vec(&[2, 2, 2, 2]));
+
+        // Bidi_Class: R EN BN ES EN (W4)
+        let bidi_info = BidiInfo::new("א1\u{00AD}+2", None);
+        assert_eq!(bidi_info.levels, Level::vec(&[1, 1, 2, 2, 2, 2, 2]));
+
+        // Bidi_Class: L BN WS L (N1)
+        let bidi_info = BidiInfo::new("a\u{00AD} b", Some(RTL_LEVEL));
+        assert_eq!(bidi_info.levels, Level::vec(&[2, 2, 2, 2, 2]));
+    }
+
+    #[test]
+    fn test_implicit_marks() {
+        // Without a mark, the neutrals between R and eos (L) get the embedding direction (N2).
+        // Bidi_Class: R R R WS ON ON
+        let bidi_info = BidiInfo::new("אבג !!", Some(LTR_LEVEL));
+        assert_eq!(bidi_info.levels, Level::vec(&[1, 1, 1, 1, 1, 1, 0, 0, 0]));
+
+        // An RLM after them makes them R (N1), and keeps its own class and level.
+        // Bidi_Class: R R R WS ON ON R
+        let bidi_info = BidiInfo::new("אבג !!\u{200F}", Some(LTR_LEVEL));
+        assert_eq!(bidi_info.original_classes[9..], [R, R, R]);
+        assert_eq!(
+            bidi_info.levels,
+            Level::vec(&[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1])
+        );
+        assert_eq!(bidi_info.reorder_line(&bidi_info.paragraphs[0], 0..12), "\u{200F}!! גבא");
+
+        // An LRM between R and neutrals makes them L in an RTL paragraph.
+        // Bidi_Class: L WS ON L R
+        let bidi_info = BidiInfo::new("a !\u{200E}א", Some(RTL_LEVEL));
+        assert_eq!(bidi_info.levels, Level::vec(&[2, 2, 2, 2, 2, 2, 1, 1]));
+
+        // An ALM makes a following number AN (W2).
+        // Bidi_Class: L AL EN
+        let bidi_info = BidiInfo::new("a\u{061C}1", Some(LTR_LEVEL));
+        assert_eq!(bidi_info.levels, Level::vec(&[0, 1, 1, 2]));
+    }
+
+    #[test]
+    fn test_from_classes() {
+        let classes = [L, WS, R, WS, AL, EN, B, FSI, R, PDI, ON];
+        let info = BidiInfo::from_classes(&classes, None);
+        assert_eq!(
+            info.original_classes,
+            vec![L, WS, R, WS, AL, EN, B, RLI, R, PDI, ON]
+        );
+        assert_eq!(info.levels, Level::vec(&[0, 0, 1, 1, 1, 2, 0, 0, 1, 0, 0]));
+        assert_eq!(
+            info.paragraphs,
+            vec![
+                ParagraphInfo { range: 0..7, level: LTR_LEVEL },
+                ParagraphInfo { range: 7..11, level: LTR_LEVEL },
+            ]
+        );
+        assert!(info.has_rtl());
+
+        // Same as running the algorithm on text with these classes.
+        let text = "a א \u{0627}1\n\u{2068}א\u{2069}!";
+        let bidi_info = BidiInfo::new(text, None);
+        let levels: Vec<Level> = text.char_indices().map(|(i, _)| bidi_info.levels[i]).collect();
+        assert_eq!(info.levels, levels);
+
+        let info = BidiInfo::from_classes(&[ON, EN], Some(RTL_LEVEL));
+        assert_eq!(info.levels, Level::vec(&[1, 2]));
+        assert_eq!(BidiInfo::from_classes(&[], None).paragraphs, vec![]);
+    }
+
+    #[test]
+    fn test_new_latin1() {
+        // "café 12<NEL>«1<SHY>+2»", where NEL (0x85) is a paragraph separator.
+        let text = b"caf\xE9 12\x85\xAB1\xAD+2\xBB";
+
+        let bidi_info = BidiInfo::new_latin1(text, None);
+        assert_eq!(
+            bidi_info,
+            Latin1BidiInfo {
+                text,
+                original_classes: vec![L, L, L, L, WS, EN, EN, B, ON, EN, BN, ES, EN, ON],
+                levels: Level::vec(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..8,
+                        level: LTR_LEVEL,
+                    },
+                    ParagraphInfo {
+                        range: 8..14,
+                        level: LTR_LEVEL,
+                    },
+                ],
+            }
+        );
+        assert!(!bidi_info.has_rtl());
+
+        // No Latin-1 character is strong RTL, but the paragraph level can still be forced.
+        let bidi_info = BidiInfo::new_latin1(text, Some(RTL_LEVEL));
+        assert_eq!(
+            bidi_info.levels,
+            Level::vec(&[2, 2, 2, 2, 2, 2, 2, 1, 1, 2, 2, 2, 2, 1])
+        );
+        assert_eq!(bidi_info.paragraphs[0].level, RTL_LEVEL);
+        assert_eq!(bidi_info.paragraphs[1].level, RTL_LEVEL);
+        assert!(bidi_info.has_rtl());
+
+        assert_eq!(BidiInfo::new_latin1(b"", None).paragraphs, vec![]);
+    }
+
+    #[test]
+    fn test_new_utf16() {
+        // "a\u{1F600}\nאב", where U+1F600 is a surrogate pair.
+        let text: Vec<u16> = "a\u{1F600}\nאב".encode_utf16().collect();
+        assert_eq!(
+            BidiInfo::new_utf16(&text, None),
+            Utf16BidiInfo {
+                text: &text,
+                original_classes: vec![L, ON, ON, B, R, R],
+                levels: Level::vec(&[0, 0, 0, 0, 1, 1]),
+                paragraphs: vec![
+                    ParagraphInfo {
+                        range: 0..4,
+                        level: LTR_LEVEL,
+                    },
+                    ParagraphInfo {
+                        range: 4..6,
+                        level: RTL_LEVEL,
+                    },
+                ],
+            }
+        );
+        assert_eq!(BidiInfo::new_utf16(&[], None).paragraphs, vec![]);
+    }
+
+    #[test]
+    fn test_new_utf16_unpaired_surrogates() {
+        // A lone high surrogate and a lone low surrogate within RTL text in an LTR paragraph.
+        let text = [0x05D0, 0xD800, 0x05D1, 0x20, 0xDC00, 0x0061];
+
+        let bidi_info = BidiInfo::new_utf16(&text, Some(LTR_LEVEL));
+        assert_eq!(bidi_info.original_classes, vec![R, BN, R, WS, BN, L]);
+        assert_eq!(bidi_info.levels, Level::vec(&[1, 1, 1, 0, 0, 0]));
+        assert_eq!(bidi_info.paragraphs[0].range, 0..6);
+
+        let bidi_info = BidiInfo::new_utf16_with_surrogates(
+            &text,
+            Some(LTR_LEVEL),
+            UnpairedSurrogates::AsOtherNeutral,
+        );
+        assert_eq!(bidi_info.original_classes, vec![R, ON, R, WS, ON, L]);
+        assert_eq!(bidi_info.levels, Level::vec(&[1, 1, 1, 0, 0, 0]));
+        assert_eq!(bidi_info.paragraphs[0].range, 0..6);
+
+        // Surrogates at the ends of the text, and a low surrogate before a high one.
+        let text = [0xDC00, 0xD800, 0x05D0, 0xD800];
+        let bidi_info = BidiInfo::new_utf16(&text, None);
+        assert_eq!(bidi_info.original_classes, vec![BN, BN, R, BN]);
+        assert_eq!(bidi_info.levels, Level::vec(&[1, 1, 1, 1]));
+        assert_eq!(bidi_info.paragraphs[0].range, 0..4);
+    }
+
+    #[test]
+    fn test_paragraph() {
+        let text = "abc\nאבג\nabc אבג";
+        let bidi_info = BidiInfo::new(text, None);
+        let paras: Vec<_> = bidi_info
+            .paragraphs
+            .iter()
+            .map(|para| Paragraph::new(&bidi_info, para))
+            .collect();
+        assert_eq!(paras.len(), 3);
+
+        assert_eq!(paras[0].direction(), Direction::Ltr);
+        assert_eq!(paras[1].direction(), Direction::Rtl);
+        assert_eq!(paras[2].direction(), Direction::Mixed);
+
+        // Positions are relative to the start of the paragraph.
+        assert_eq!(paras[0].level_at(0), LTR_LEVEL);
+        assert_eq!(paras[1].level_at(0), RTL_LEVEL);
+        assert_eq!(paras[2].level_at(0), LTR_LEVEL);
+        assert_eq!(paras[2].level_at(4), RTL_LEVEL);
+
+        for para in &paras {
+            assert_eq!(
+                para.reorder_line(para.para.range.clone()),
+                bidi_info.reorder_line(para.para, para.para.range.clone())
+            );
+        }
+        assert_eq!(paras[1].reorder_line(paras[1].para.range.clone()), "\nגבא");
+        assert_eq!(paras[2].reorder_line(paras[2].para.range.clone()), "abc גבא");
+    }
+
+    #[test]
+    #[should_panic(expected = "position outside of the paragraph")]
+    fn test_paragraph_level_at_panics() {
+        let bidi_info = BidiInfo::new("abc\nאבג", None);
+        // The first byte of the next paragraph
+        Paragraph::new(&bidi_info, &bidi_info.paragraphs[0]).level_at(4);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut bidi_info = BidiInfo::new("abc\nאבג\nabc", None);
+        bidi_info.original_classes.reserve(100);
+        bidi_info.levels.reserve(100);
+        bidi_info.paragraphs.reserve(100);
+        let before = bidi_info.clone();
+
+        bidi_info.shrink_to_fit();
+        assert_eq!(bidi_info, before);
+        assert_eq!(bidi_info.original_classes.capacity(), bidi_info.original_classes.len());
+        assert_eq!(bidi_info.levels.capacity(), bidi_info.levels.len());
+        assert_eq!(bidi_info.paragraphs.capacity(), bidi_info.paragraphs.len());
+    }
+
+    #[test]
+    fn test_memory_footprint() {
+        let short = BidiInfo::new("abc אבג", None);
+        let long_text = "abc אבג\n".repeat(100);
+        let long = BidiInfo::new(&long_text, None);
+        assert!(short.memory_footprint() > 0);
+        assert!(long.memory_footprint() > short.memory_footprint());
+
+        // Spare capacity is counted.
+        let mut bidi_info = BidiInfo::new("abc", None);
+        let before = bidi_info.memory_footprint();
+        bidi_info.levels.reserve(100);
+        assert!(bidi_info.memory_footprint() >= before + 100 * mem::size_of::<Level>());
+        bidi_info.shrink_to_fit();
+        assert!(bidi_info.memory_footprint() <= before);
+        assert_eq!(BidiInfo::new("", None).memory_footprint(), 0);
+    }
+
+    #[test]
+    fn test_validate() {
+        let text = "abc\n\u{2068}אבג\u{2069}";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.validate(), Ok(()));
+        assert_eq!(BidiInfo::new("", None).validate(), Ok(()));
+
+        let mut invalid = bidi_info.clone();
+        invalid.levels.pop();
+        assert_eq!(
+            invalid.validate(),
+            Err(BidiError::MismatchedLength { expected: 16, actual: 15 })
+        );
+
+        let mut invalid = bidi_info.clone();
+        invalid.original_classes.push(L);
+        assert_eq!(
+            invalid.validate(),
+            Err(BidiError::MismatchedLength { expected: 16, actual: 17 })
+        );
+
+        let mut invalid = bidi_info.clone();
+        let level = Level::new(Level::max_implicit_depth()).unwrap();
+        invalid.paragraphs[1].level = level;
+        assert_eq!(invalid.validate(), Err(BidiError::LevelTooDeep { level }));
+
+        let mut invalid = bidi_info.clone();
+        invalid.paragraphs[1].range.start = 3;
+        assert_eq!(invalid.validate(), Err(BidiError::InvalidParagraphRange { index: 1 }));
+
+        let mut invalid = bidi_info.clone();
+        invalid.paragraphs.pop();
+        assert_eq!(invalid.validate(), Err(BidiError::InvalidParagraphRange { index: 1 }));
+
+        let mut invalid = bidi_info.clone();
+        invalid.paragraphs[1].range.end = 20;
+        assert_eq!(invalid.validate(), Err(BidiError::InvalidParagraphRange { index: 1 }));
+
+        let mut invalid = bidi_info.clone();
+        invalid.paragraphs[1].range.start = 5;
+        invalid.paragraphs[0].range.end = 5;
+        assert_eq!(invalid.validate(), Err(BidiError::NonCharBoundary { index: 5 }));
+
+        // The FSI was resolved to RLI, which is fine, but the PDI must stay a PDI.
+        assert_eq!(bidi_info.original_classes[4], RLI);
+        let mut invalid = bidi_info.clone();
+        for class in &mut invalid.original_classes[13..16] {
+            *class = ON;
+        }
+        assert_eq!(invalid.validate(), Err(BidiError::InconsistentClass { index: 13 }));
+    }
+
+    #[test]
+    fn test_paragraph_display_order() {
+        let bidi_info = BidiInfo::new("abc\nאבג\nabc", None);
+        assert_eq!(bidi_info.paragraph_display_order(), vec![0, 1, 2]);
+
+        // RTL paragraphs are not reordered either.
+        let bidi_info = BidiInfo::new("אבג\nדהו\n", None);
+        assert!(bidi_info.paragraphs.iter().all(|para| para.level.is_rtl()));
+        assert_eq!(bidi_info.paragraph_display_order(), vec![0, 1]);
+
+        assert_eq!(BidiInfo::new("", None).paragraph_display_order(), vec![]);
+    }
+
+    #[test]
+    fn test_paragraphs_iter() {
+        let text = "abc\nאבג\u{2029}abc אבג";
+        let bidi_info = BidiInfo::new(text, None);
+        let paras: Vec<_> = bidi_info
+            .paragraphs_iter()
+            .map(|para| (&text[para.para.range.clone()], para.direction()))
+            .collect();
+        assert_eq!(
+            paras,
+            vec![
+                ("abc\n", Direction::Ltr),
+                ("אבג\u{2029}", Direction::Rtl),
+                ("abc אבג", Direction::Mixed),
+            ]
+        );
+
+        assert_eq!(BidiInfo::new("", None).paragraphs_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_requires_bidi() {
+        assert!(!requires_bidi(""));
+        assert!(!requires_bidi("Hello, world! 123 + 456 = 579\n\tfoo"));
+        assert!(requires_bidi("abc אבג"));
+        assert!(requires_bidi("abc \u{2067}def\u{2069}"));
+        assert!(requires_bidi("1\u{0660}"));
+
+        // The scan stops at the first character that requires bidi processing.
+        let text = "abc אבג def";
+        let mut count = 0;
+        assert!(chars_require_bidi(text.chars().inspect(|_| count += 1)));
+        assert_eq!(count, 5);
+    }
+
+    #[test]
+    fn test_analyze_in_context() {
+        // An RTL fragment pasted into an LTR document
+        let fragment = "אבג 12";
+        let levels = analyze_in_context(fragment, LTR_LEVEL);
+        assert_eq!(levels, Level::vec(&[1, 1, 1, 1, 1, 1, 1, 2, 2]));
+        let document = format!("abc {} def", fragment);
+        let bidi_info = BidiInfo::new(&document, None);
+        assert_eq!(levels[..], bidi_info.levels[4..4 + fragment.len()]);
+
+        // An LTR fragment pasted into an RTL document, and into an embedding.
+        let fragment = "ab, cd";
+        let document = format!("אב {} גד", fragment);
+        let bidi_info = BidiInfo::new(&document, None);
+        assert_eq!(
+            analyze_in_context(fragment, RTL_LEVEL)[..],
+            bidi_info.levels[5..5 + fragment.len()]
+        );
+        let document = format!("abc \u{202B}אב {} גד\u{202C}", fragment);
+        let bidi_info = BidiInfo::new(&document, None);
+        assert_eq!(
+            analyze_in_context(fragment, RTL_LEVEL)[..],
+            bidi_info.levels[12..12 + fragment.len()]
+        );
+        assert_eq!(
+            analyze_in_context(fragment, Level::new(3).unwrap()),
+            vec![Level::new(4).unwrap(); 6]
+        );
+    }
+
+    #[test]
+    fn test_suspicious_control_nesting() {
+        // An LRO left open across a PDI
+        let text = "a\u{2067}b\u{202D}cd\u{2069}e";
+        assert_eq!(suspicious_control_nesting(text), vec![5..13]);
+        assert!(text[5..].starts_with(chars::LRO));
+        assert!(text[..13].ends_with(chars::PDI));
+
+        // Nested overrides are all reported, but not embeddings or overrides closed by a PDF.
+        let text =
+            "\u{2066}\u{202E}a\u{202B}\u{202D}b\u{2069}\u{2066}\u{202E}c\u{202C}\u{2069}";
+        assert_eq!(suspicious_control_nesting(text), vec![10..17, 3..17]);
+
+        // Overrides that are still open at the end of a paragraph, or that enclose an isolate.
+        for text in &["\u{2067}\u{202E}a\n\u{2069}", "\u{202E}a\u{2066}b\u{2069}", "abc"] {
+            assert_eq!(suspicious_control_nesting(text), vec![]);
+        }
+
+        // Spans are byte ranges into the whole text.
+        let text = "\u{202E}\nab\u{2066}\u{202E}\u{2069}";
+        assert_eq!(suspicious_control_nesting(text), vec![9..15]);
+    }
+
+    #[test]
+    fn test_affects_neighbors() {
+        assert!(affects_neighbors('a'));
+        assert!(!affects_neighbors(' '));
+        assert!(affects_neighbors(chars::LRI));
+
+        assert!(affects_neighbors('א'));
+        assert!(affects_neighbors('1'));
+        assert!(affects_neighbors(','));
+        assert!(affects_neighbors('\u{0300}'));
+        assert!(affects_neighbors('\n'));
+        assert!(affects_neighbors('\t'));
+        assert!(!affects_neighbors('!'));
+        assert!(!affects_neighbors('\u{2003}'));
+    }
+
+    #[test]
+    fn test_isolating_run_sequences() {
+        // "ab RLI cd LRI ef PDI gh PDI ij", in a second paragraph
+        let text = "\nab\u{2067}cd\u{2066}ef\u{2069}gh\u{2069}ij";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[1];
+        let sequences = bidi_info.isolating_run_sequences(para);
+        let texts: Vec<String> = sequences.iter().map(|seq| seq.text(&bidi_info)).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "ab\u{2067}\u{2069}ij",
+                "cd\u{2066}\u{2069}gh",
+                "ef",
+            ]
+        );
+        assert_eq!(sequences[0].runs, vec![1..6, 18..23]);
+        assert_eq!(sequences[0].sos, L);
+        assert_eq!(sequences[1].sos, R);
+        assert_eq!(sequences[2].sos, L);
+    }
+
+    #[test]
+    fn test_summary() {
+        let text = "abc\nאבג 123\n\u{202B}abc\u{202C}\nabc אבג";
+        assert_eq!(
+            BidiInfo::new(text, None).summary(),
+            BidiSummary {
+                paragraph_count: 4,
+                rtl_paragraph_count: 1,
+                max_level: Level::new(2).unwrap(),
+                has_rtl: true,
+            }
+        );
+        assert_eq!(
+            BidiInfo::new("abc\n123", None).summary(),
+            BidiSummary {
+                paragraph_count: 2,
+                rtl_paragraph_count: 0,
+                max_level: LTR_LEVEL,
+                has_rtl: false,
+            }
+        );
+        assert_eq!(BidiInfo::new("", None).summary().paragraph_count, 0);
+        assert_eq!(BidiInfo::new("", None).summary().max_level, LTR_LEVEL);
+    }
+
+    #[test]
+    fn test_direction_stats() {
+        let bidi_info = BidiInfo::new("ab אבג\nabc", None);
+        assert_eq!(
+            bidi_info.direction_stats(&bidi_info.paragraphs[0]),
+            DirectionStats {
+                ltr_chars: 4,
+                rtl_chars: 3,
+                run_count: 3,
+            }
+        );
+        assert_eq!(
+            bidi_info.direction_stats(&bidi_info.paragraphs[1]),
+            DirectionStats {
+                ltr_chars: 3,
+                rtl_chars: 0,
+                run_count: 1,
+            }
+        );
+
+        // Half LTR, half RTL.
+        let bidi_info = BidiInfo::new("א 12", None);
+        assert_eq!(
+            bidi_info.direction_stats(&bidi_info.paragraphs[0]),
+            DirectionStats {
+                ltr_chars: 2,
+                rtl_chars: 2,
+                run_count: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_accessors() {
+        let text = "ab אב\ncd";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.text(), text);
+        assert_eq!(bidi_info.original_classes(), &[L, L, WS, R, R, R, R, B, L, L]);
+        assert_eq!(bidi_info.levels(), &Level::vec(&[0, 0, 0, 1, 1, 1, 1, 0, 0, 0])[..]);
+        assert_eq!(
+            bidi_info.paragraphs(),
+            &[
+                ParagraphInfo {
+                    range: 0..8,
+                    level: LTR_LEVEL,
+                },
+                ParagraphInfo {
+                    range: 8..10,
+                    level: LTR_LEVEL,
+                },
+            ]
+        );
+
+        // The text outlives the `BidiInfo`.
+        let text_ref = BidiInfo::new(text, None).text();
+        assert_eq!(text_ref, text);
+    }
+
+    #[test]
+    fn test_clone_and_debug() {
+        let bidi_info = BidiInfo::new("ab\nאב", None);
+        assert_eq!(bidi_info.clone(), bidi_info);
+
+        let debug = format!("{:?}", bidi_info);
+        assert!(debug.starts_with("BidiInfo { text: \"ab\\nאב\""));
+        assert!(debug.contains("ParagraphInfo { range: 0..3, level: Level(0) }"));
+        assert!(debug.contains("ParagraphInfo { range: 3..7, level: Level(1) }"));
+
+        let para = bidi_info.paragraphs_iter().nth(1).unwrap();
+        assert_eq!(para.clone(), para);
+        assert!(format!("{:?}", para).starts_with("Paragraph { info: BidiInfo {"));
+
+        let initial_info = InitialInfo::new("ab", None);
+        assert_eq!(initial_info.clone(), initial_info);
+        let sequences = bidi_info.isolating_run_sequences(&bidi_info.paragraphs[0]);
+        assert_eq!(sequences.clone(), sequences);
+    }
+
+    #[test]
+    fn test_prefer_isolates() {
+        fn levels(text: &str) -> Vec<Level> {
+            let bidi_info = BidiInfo::new(text, None);
+            text.char_indices()
+                .filter(|&(_, c)| !chars::is_explicit_format_char(c))
+                .map(|(i, _)| bidi_info.levels[i])
+                .collect()
+        }
+
+        // A balanced RLE ... PDF span
+        let text = "abc \u{202B}אבג 123\u{202C} def";
+        let result = prefer_isolates(text);
+        assert_eq!(result, "abc \u{2067}אבג 123\u{2069} def");
+        assert_eq!(levels(&result), levels(text));
+
+        // Nested spans
+        let text = "abc \u{202B}אבג \u{202A}de\u{202C}\u{202C}.";
+        let result = prefer_isolates(text);
+        assert_eq!(result, "abc \u{2067}אבג \u{2066}de\u{2069}\u{2069}.");
+        assert_eq!(levels(&result), levels(text));
+
+        // Each paragraph is checked on its own.  In the second one, only the last span can be
+        // replaced: an isolate at its start would hide its first strong character from P2.
+        let text = "abc \u{202B}אבג\u{202C}\n\u{202B}אבג\u{202C} xyz \u{202B}דה\u{202C}";
+        let result = prefer_isolates(text);
+        assert_eq!(
+            result,
+            "abc \u{2067}אבג\u{2069}\n\u{202B}אבג\u{202C} xyz \u{2067}דה\u{2069}"
+        );
+        assert_eq!(levels(&result), levels(text));
+
+        // Untouched: overrides, unmatched embeddings, and spans that would change the paragraph
+        // level since P2 skips isolates.
+        for text in &[
+            "abc \u{202E}def\u{202C}",
+            "abc \u{202B}def",
+            "abc\u{202C}",
+            "\u{202B}אבג\u{202C}",
+            "\u{202B}abc\u{2069}\u{202C}",
+        ] {
+            assert_eq!(prefer_isolates(text), Cow::Borrowed(*text));
+        }
+        assert_eq!(prefer_isolates(""), "");
+    }
+
+    #[test]
+    fn test_isolate() {
+        // An unbalanced RLI is removed.
+        let result = isolate("abc\u{2067}def", Some(ResolvedDirection::Ltr));
+        assert_eq!(result, "\u{2066}abcdef\u{2069}");
+        let wrapped = format!("אבג {} 123", result);
+        let bidi_info = BidiInfo::new(&wrapped, None);
+        assert_eq!(bidi_info.paragraphs[0].level, RTL_LEVEL);
+        assert_eq!(bidi_info.levels[wrapped.len() - 3..], [Level::new(2).unwrap(); 3]);
+
+        // Balanced controls are kept, and stray or unmatched ones removed.
+        assert_eq!(
+            isolate("\u{2069}a\u{2067}b\u{202B}c\u{2069}\u{202C}", Some(ResolvedDirection::Rtl)),
+            "\u{2067}a\u{2067}bc\u{2069}\u{2069}"
+        );
+        assert_eq!(
+            isolate("\u{202A}a\u{202C}\u{202E}b\u{2066}c\u{2069}", None),
+            "\u{2068}\u{202A}a\u{202C}b\u{2066}c\u{2069}\u{2069}"
+        );
+        assert_eq!(isolate("\u{2068}a\nb\u{2069}", None), "\u{2068}a\nb\u{2069}");
+        assert_eq!(isolate("", None), "\u{2068}\u{2069}");
+
+        // The result is always balanced.
+        for text in &[
+            "\u{2067}\u{2067}\u{202B}",
+            "\u{202C}\u{2069}",
+            "a\u{2066}\u{202D}b\u{2069}",
+        ] {
+            let result = isolate(text, None);
+            let (mut isolates, mut embeddings) = (0, 0);
+            for c in result.chars() {
+                match c {
+                    chars::LRI | chars::RLI | chars::FSI => isolates += 1,
+                    chars::PDI => isolates -= 1,
+                    chars::LRE | chars::RLE | chars::LRO | chars::RLO => embeddings += 1,
+                    chars::PDF => embeddings -= 1,
+                    _ => {}
+                }
+                assert!(isolates >= 0 && embeddings >= 0);
+            }
+            assert_eq!((isolates, embeddings), (0, 0), "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_rtl_scripts_present() {
+        assert_eq!(
+            rtl_scripts_present("abc אבג"),
+            RtlScripts { hebrew: true, arabic: false, other: false }
+        );
+        assert_eq!(
+            rtl_scripts_present("abc \u{0627}\u{0644} \u{0661}"),
+            RtlScripts { hebrew: false, arabic: true, other: false }
+        );
+        assert_eq!(
+            rtl_scripts_present("\u{07CA} \u{FB1D}\u{0660}"),
+            RtlScripts { hebrew: true, arabic: true, other: true }
+        );
+        assert_eq!(rtl_scripts_present("abc 123"), RtlScripts::default());
+    }
+
+    #[test]
+    fn test_weak_fsi_spans() {
+        // An FSI containing only digits defaults to LTR.
+        let text = "a\u{2068}123\u{2069}b";
+        assert_eq!(weak_fsi_spans(text), vec![1..10]);
+        assert_eq!(&text[1..10], "\u{2068}123\u{2069}");
+
+        // FSIs containing a strong character are not reported.
+        assert_eq!(weak_fsi_spans("\u{2068}1א\u{2069}\u{2068}a\u{2069}"), vec![]);
+
+        // Strong characters within nested isolates don't count.
+        assert_eq!(weak_fsi_spans("\u{2068}\u{2067}א\u{2069}\u{2069}"), vec![0..14]);
+        assert_eq!(weak_fsi_spans("\u{2068}1\u{2068}2\u{2069}\u{2069}"), vec![0..14, 4..11]);
+
+        // Unmatched FSIs end with their paragraph.
+        assert_eq!(weak_fsi_spans("\u{2068}1\nא"), vec![0..4]);
+        assert_eq!(weak_fsi_spans("a\u{2068}1 "), vec![1..6]);
+    }
+
+    #[test]
+    fn test_same_level_run() {
+        let bidi_info = BidiInfo::new("abc אבג def אבג", None);
+        // Within one RTL run
+        assert!(bidi_info.same_level_run(4, 8));
+        assert!(bidi_info.same_level_run(8, 4));
+        assert!(bidi_info.same_level_run(5, 5));
+        // Across a level boundary
+        assert!(!bidi_info.same_level_run(2, 4));
+        // Same level, but with another level between them
+        assert!(!bidi_info.same_level_run(4, 19));
+        assert!(!bidi_info.same_level_run(0, 15));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_same_level_run_panics() {
+        let bidi_info = BidiInfo::new("abc", None);
+        bidi_info.same_level_run(1, 3);
+    }
+
+    #[test]
+    fn test_iter() {
+        let text = "aב 1\u{0661}";
+        let bidi_info = BidiInfo::new(text, None);
+        let triples: Vec<(char, Level, BidiClass)> = bidi_info.iter().collect();
+        assert_eq!(
+            triples,
+            vec![
+                ('a', LTR_LEVEL, L),
+                ('ב', RTL_LEVEL, R),
+                (' ', RTL_LEVEL, WS),
+                ('1', Level::new(2).unwrap(), EN),
+                ('\u{0661}', Level::new(2).unwrap(), AN),
+            ]
+        );
+        for ((i, c), triple) in text.char_indices().zip(bidi_info.iter()) {
+            assert_eq!(triple, (c, bidi_info.levels()[i], bidi_info.original_classes()[i]));
+        }
+        assert_eq!(BidiInfo::new("", None).iter().count(), 0);
+    }
+
+    #[test]
+    fn test_word_direction() {
+        // A word straddling an LTR/RTL boundary takes the direction of its first strong character.
+        let text = "ab אבcd גד";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.word_direction(3..9), ResolvedDirection::Rtl);
+        assert_eq!(bidi_info.word_direction(0..7), ResolvedDirection::Ltr);
+        assert_eq!(bidi_info.word_direction(3..7), ResolvedDirection::Rtl);
+
+        // Without strong characters, the resolved level decides.
+        let text = "אב 123, ab 45";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.word_direction(5..8), ResolvedDirection::Ltr);
+        assert_eq!(bidi_info.word_direction(8..9), ResolvedDirection::Rtl);
+        assert_eq!(bidi_info.word_direction(9..15), ResolvedDirection::Ltr);
+    }
+
+    #[test]
+    fn test_char_directions() {
+        use ResolvedDirection::{Ltr, Rtl};
+
+        let bidi_info = BidiInfo::new("ab אב 12", None);
+        let directions = bidi_info.char_directions();
+        assert_eq!(directions, vec![Ltr, Ltr, Ltr, Rtl, Rtl, Rtl, Ltr, Ltr]);
+        for ((i, _), direction) in bidi_info.text.char_indices().zip(directions) {
+            assert_eq!(direction == Rtl, bidi_info.levels[i].is_rtl());
+        }
+
+        let bidi_info = BidiInfo::new("אב 12", None);
+        assert_eq!(bidi_info.char_directions(), vec![Rtl, Rtl, Rtl, Ltr, Ltr]);
+        assert_eq!(BidiInfo::new("", None).char_directions(), vec![]);
+    }
+
+    #[test]
+    fn test_controls_to_force() {
+        // An LTR span inside an RTL paragraph.
+        let text = "אבג abc 123 דהו";
+        let bidi_info = BidiInfo::new(text, None);
+        assert!(bidi_info.paragraphs[0].level.is_rtl());
+        let span = 7..14;
+        let (start, end) = bidi_info.controls_to_force(span.clone(), ResolvedDirection::Ltr);
+        assert_eq!((start, end), (chars::LRI, chars::PDI));
+
+        let mut forced = String::from(&text[..span.start]);
+        forced.push(start);
+        forced.push_str(&text[span.clone()]);
+        forced.push(end);
+        forced.push_str(&text[span.end..]);
+        let forced_info = BidiInfo::new(&forced, None);
+        let para = &forced_info.paragraphs[0];
+        assert_eq!(
+            forced_info.reorder_line(para, para.range.clone()),
+            "והד \u{2069}abc 123\u{2066} גבא"
+        );
+
+        // The span would have to be isolated to display it RTL too, since it has L characters.
+        let controls = bidi_info.controls_to_force(span, ResolvedDirection::Rtl);
+        assert_eq!(controls, (chars::RLI, chars::PDI));
+
+        // An RTL span already displays RTL in an RTL paragraph.  The marks keep the space at its
+        // end with it.
+        let controls = bidi_info.controls_to_force(0..7, ResolvedDirection::Rtl);
+        assert_eq!(controls, (chars::RLM, chars::RLM));
+        let controls = bidi_info.controls_to_force(0..7, ResolvedDirection::Ltr);
+        assert_eq!(controls, (chars::LRI, chars::PDI));
+
+        // An LTR span in an LTR paragraph, but within an RTL embedding.
+        let bidi_info = BidiInfo::new("abc \u{202B}def\u{202C}", None);
+        assert_eq!(
+            bidi_info.controls_to_force(0..3, ResolvedDirection::Ltr),
+            (chars::LRM, chars::LRM)
+        );
+        assert_eq!(
+            bidi_info.controls_to_force(7..10, ResolvedDirection::Ltr),
+            (chars::LRI, chars::PDI)
+        );
+    }
+
+    #[test]
+    fn test_strong_bounds() {
+        // Leading neutrals and an isolate before the first strong character, which is RTL.
+        let text = "1. \u{2066}abc\u{2069} אב cd 2";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.strong_bounds(para), (Some(13), Some(19)));
+        assert_eq!(&text[13..15], "א");
+        assert!(para.level.is_rtl());
+
+        // The second paragraph, with an unmatched isolate initiator.
+        let text = "a\n!\u{2067}b";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.strong_bounds(&bidi_info.paragraphs[0]), (Some(0), Some(0)));
+        assert_eq!(bidi_info.strong_bounds(&bidi_info.paragraphs[1]), (None, None));
+    }
+
+    #[test]
+    fn test_isolate_levels() {
+        // An RLI in an LTR paragraph
+        let text = "abc \u{2067}אבג 123\u{2069} def";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.isolate_levels(para, 4), (LTR_LEVEL, RTL_LEVEL));
+        assert_eq!(bidi_info.levels[7], RTL_LEVEL);
+        assert_eq!(bidi_info.levels[14], Level::new(2).unwrap());
+
+        // Nested isolates in an RTL paragraph, and an FSI that resolves to RTL.
+        let text = "אב \u{2066}ab \u{2068}גד\u{2069}\u{2069}";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.isolate_levels(para, 5), (RTL_LEVEL, Level::new(2).unwrap()));
+        assert_eq!(
+            bidi_info.isolate_levels(para, 11),
+            (Level::new(2).unwrap(), Level::new(3).unwrap())
+        );
+
+        // Isolates that overflow the maximum depth don't raise their content.
+        let text = "\u{2066}".repeat(70);
+        let bidi_info = BidiInfo::new(&text, None);
+        let para = &bidi_info.paragraphs[0];
+        let max = Level::new(124).unwrap();
+        assert_eq!(
+            bidi_info.isolate_levels(para, 61 * 3),
+            (Level::new(122).unwrap(), max)
+        );
+        assert_eq!(bidi_info.isolate_levels(para, 62 * 3), (max, max));
+        assert_eq!(bidi_info.isolate_levels(para, text.len() - 3), (max, max));
+    }
+
+    #[test]
+    #[should_panic(expected = "not an isolate initiator")]
+    fn test_isolate_levels_panics() {
+        let bidi_info = BidiInfo::new("a\u{202B}b", None);
+        bidi_info.isolate_levels(&bidi_info.paragraphs[0], 1);
+    }
+
+    #[test]
+    fn test_direction_islands() {
+        use ResolvedDirection::{Ltr, Rtl};
+
+        // a LRE b PDF א: the levels are 0, 0, 2, 2, 1.
+        let text = "a\u{202A}b\u{202C}\u{05D0}";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.levels[4], Level::new(2).unwrap());
+        assert_eq!(
+            bidi_info.direction_islands(&bidi_info.paragraphs[0]),
+            vec![(Ltr, 0..8), (Rtl, 8..10)]
+        );
+
+        let text = "abc\nאבג 123 אבג";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.direction_islands(&bidi_info.paragraphs[0]), vec![(Ltr, 0..4)]);
+        assert_eq!(
+            bidi_info.direction_islands(&bidi_info.paragraphs[1]),
+            vec![(Rtl, 4..11), (Ltr, 11..14), (Rtl, 14..21)]
+        );
+    }
+
+    #[test]
+    fn test_bidi_info_has_rtl() {
+        // ASCII only
+        assert_eq!(BidiInfo::new("123", None).has_rtl(), false);
+        assert_eq!(BidiInfo::new("123", Some(LTR_LEVEL)).has_rtl(), false);
+        assert_eq!(BidiInfo::new("123", Some(RTL_LEVEL)).has_rtl(), false);
+        assert_eq!(BidiInfo::new("abc", None).has_rtl(), false);
+        assert_eq!(BidiInfo::new("abc", Some(LTR_LEVEL)).has_rtl(), false);
+        assert_eq!(BidiInfo::new("abc", Some(RTL_LEVEL)).has_rtl(), false);
+        assert_eq!(BidiInfo::new("abc 123", None).has_rtl(), false);
+        assert_eq!(BidiInfo::new("abc\n123", None).has_rtl(), false);
+
+        // With Hebrew
+        assert_eq!(BidiInfo::new("אבּג", None).has_rtl(), true);
+        assert_eq!(BidiInfo::new("אבּג", Some(LTR_LEVEL)).has_rtl(), true);
+        assert_eq!(BidiInfo::new("אבּג", Some(RTL_LEVEL)).has_rtl(), true);
+        assert_eq!(BidiInfo::new("abc אבּג", None).has_rtl(), true);
+        assert_eq!(BidiInfo::new("abc\nאבּג", None).has_rtl(), true);
+        assert_eq!(BidiInfo::new("אבּג abc", None).has_rtl(), true);
+        assert_eq!(BidiInfo::new("אבּג\nabc", None).has_rtl(), true);
+        assert_eq!(BidiInfo::new("אבּג 123", None).has_rtl(), true);
+        assert_eq!(BidiInfo::new("אבּג\n123", None).has_rtl(), true);
+    }
+
+    fn reorder_paras(text: &str) -> Vec<Cow<str>> {
+        let bidi_info = BidiInfo::new(text, None);
+        bidi_info
+            .paragraphs
+            .iter()
+            .map(|para| bidi_info.reorder_line(para, para.range.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_reorder_line() {
+        /// Bidi_Class: L L L B L L L B L L L
+        assert_eq!(
+            reorder_paras("abc\ndef\nghi"),
+            vec!["abc\n", "def\n", "ghi"]
+        );
+
+        /// Bidi_Class: L L EN B L L EN B L L EN
+        assert_eq!(
+            reorder_paras("ab1\nde2\ngh3"),
+            vec!["ab1\n", "de2\n", "gh3"]
+        );
+
+        /// Bidi_Class: L L L B AL AL AL
+        assert_eq!(reorder_paras("abc\nابج"), vec!["abc\n", "جبا"]);
+
+        /// Bidi_Class: AL AL AL B L L L
+        assert_eq!(reorder_paras("ابج\nabc"), vec!["\nجبا", "abc"]);
+
+        assert_eq!(reorder_paras("1.-2"), vec!["1.-2"]);
+        assert_eq!(reorder_paras("1-.2"), vec!["1-.2"]);
+        assert_eq!(reorder_paras("abc אבג"), vec!["abc גבא"]);
+
+        // Numbers being weak LTR characters, cannot reorder strong RTL
+        assert_eq!(reorder_paras("123 אבג"), vec!["גבא 123"]);
+
+        assert_eq!(reorder_paras("abc\u{202A}def"), vec!["abc\u{202A}def"]);
+
+        assert_eq!(
+            reorder_paras("abc\u{202A}def\u{202C}ghi"),
+            vec!["abc\u{202A}def\u{202C}ghi"]
+        );
+
+        assert_eq!(
+            reorder_paras("abc\u{2066}def\u{2069}ghi"),
+            vec!["abc\u{2066}def\u{2069}ghi"]
+        );
+
+        // Testing for RLE Character.  The RLE and PDF join the runs of their neighbors.
+        assert_eq!(
+            reorder_paras("\u{202B}abc אבג\u{202C}"),
+            vec!["\u{202C}גבא \u{202B}abc"]
+        );
+
+        // Testing neutral characters
+        assert_eq!(reorder_paras("אבג? אבג"), vec!["גבא ?גבא"]);
+
+        // Testing neutral characters with special case
+        assert_eq!(reorder_paras("A אבג?"), vec!["A גבא?"]);
+
+        // Testing neutral characters with Implicit RTL Marker
+        assert_eq!(
+            reorder_paras("A אבג?\u{200F}"),
+            vec!["A \u{200F}?גבא"]
+        );
+        assert_eq!(reorder_paras("אבג abc"), vec!["abc גבא"]);
+        assert_eq!(
+            reorder_paras("abc\u{2067}.-\u{2069}ghi"),
+            vec!["abc\u{2067}-.\u{2069}ghi"]
+        );
+
+        assert_eq!(
+            reorder_paras("Hello, \u{2068}\u{202E}world\u{202C}\u{2069}!"),
+            vec!["Hello, \u{2068}\u{202E}\u{202C}dlrow\u{2069}!"]
+        );
+
+        // With mirrorable characters in RTL run
+        assert_eq!(reorder_paras("א(ב)ג."), vec![".ג)ב(א"]);
+
+        // With mirrorable characters on level boundry
+        assert_eq!(
+            reorder_paras("אב(גד[&ef].)gh"),
+            vec!["ef].)gh&[דג(בא"]
+        );
+    }
+
+    #[test]
+    fn test_reorder_line_with_removed() {
+        let text = "a\u{202B}bc אב\u{202C}d";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let line = para.range.clone();
+        let reorder = |removed| bidi_info.reorder_line_with_removed(para, line.clone(), removed);
+
+        assert_eq!(reorder(RemovedChars::Keep), bidi_info.reorder_paragraph(para));
+        assert_eq!(reorder(RemovedChars::Keep), "a\u{202B}\u{202C}בא bcd");
+        assert_eq!(reorder(RemovedChars::Drop), "aבא bcd");
+        assert_eq!(
+            reorder(RemovedChars::Replace('\u{FFFC}')),
+            "a\u{FFFC}\u{FFFC}בא bcd"
+        );
+        assert_eq!(RemovedChars::default(), RemovedChars::Keep);
+    }
+
+    #[test]
+    fn test_reorder_line_with_offsets() {
+        let text = "ab אבג cd\nשׁ";
+        let bidi_info = BidiInfo::new(text, None);
+
+        let para = &bidi_info.paragraphs[0];
+        let (display, offsets) = bidi_info.reorder_line_with_offsets(para, para.range.clone());
+        assert_eq!(display, bidi_info.reorder_line(para, para.range.clone()));
+        assert_eq!(display, "ab גבא cd\n");
+        assert_eq!(offsets, vec![0, 1, 2, 7, 5, 3, 9, 10, 11, 12]);
+        for (c, &offset) in display.chars().zip(offsets.iter()) {
+            assert_eq!(text[offset..].chars().next(), Some(c));
+        }
+
+        // Offsets are relative to the whole text, not to the line.
+        let para = &bidi_info.paragraphs[1];
+        let (display, offsets) = bidi_info.reorder_line_with_offsets(para, para.range.clone());
+        assert_eq!(display, "ש\u{5c1}");
+        assert_eq!(offsets, vec![13, 15]);
+    }
+
+    // <http://www.unicode.org/reports/tr9/#L3>
+    #[test]
+    fn test_reorder_line_keeps_marks_after_base() {
+        // Bidi_Class: L WS R NSM NSM R WS L
+        let text = "a \u{05D1}\u{05B0}\u{05BC}\u{05D2} b";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let line = para.range.clone();
+        assert_eq!(
+            bidi_info.reorder_line(para, line.clone()),
+            "a \u{05D2}\u{05D1}\u{05B0}\u{05BC} b"
+        );
+        let (_, offsets) = bidi_info.reorder_line_with_offsets(para, line.clone());
+        assert_eq!(offsets, vec![0, 1, 8, 2, 4, 6, 10, 11]);
+        let chars: String =
+            bidi_info.reorder_line_with_levels(para, line).iter().map(|&(c, _)| c).collect();
+        assert_eq!(chars, "a \u{05D2}\u{05D1}\u{05B0}\u{05BC} b");
+
+        // Marks without a base in the run stay reversed.
+        let text = "\u{05B0}\u{05BC}\u{05D1}";
+        let bidi_info = BidiInfo::new(text, Some(RTL_LEVEL));
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(
+            bidi_info.reorder_line(para, para.range.clone()),
+            "\u{05D1}\u{05BC}\u{05B0}"
+        );
+        let text = "\u{05B0}\u{05D1}\u{05BC}\u{05D2}";
+        let bidi_info = BidiInfo::new(text, Some(RTL_LEVEL));
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(
+            bidi_info.reorder_line(para, para.range.clone()),
+            "\u{05D2}\u{05D1}\u{05BC}\u{05B0}"
+        );
+    }
+
+    #[test]
+    fn test_reorder_line_with_levels() {
+        let text = "ab אב 12 ";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let line = para.range.clone();
+        let pairs = bidi_info.reorder_line_with_levels(para, line.clone());
+
+        let chars: String = pairs.iter().map(|&(c, _)| c).collect();
+        assert_eq!(chars, bidi_info.reorder_line(para, line.clone()));
+        assert_eq!(chars, "ab 12 בא ");
+        assert_eq!(
+            pairs.iter().map(|&(_, l)| l).collect::<Vec<Level>>(),
+            Level::vec(&[0, 0, 0, 2, 2, 1, 1, 1, 0])
+        );
+
+        // Each character carries the level of its source character, after L1.
+        let (levels, _) = bidi_info.visual_runs(para, line.clone());
+        let (_, offsets) = bidi_info.reorder_line_with_offsets(para, line);
+        for (&(c, level), &i) in pairs.iter().zip(&offsets) {
+            assert_eq!(text[i..].chars().next(), Some(c));
+            assert_eq!(level, levels[i]);
+        }
+    }
+
+    #[test]
+    fn test_debug_visual() {
+        let text = "abc אבג 123 def";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(
+            bidi_info.debug_visual(para, para.range.clone()),
+            "[0]abc |[2]123|[1] גבא|[0] def"
+        );
+        assert_eq!(bidi_info.debug_visual(para, 0..4), "[0]abc ");
+
+        // Without the markers, this is the re-ordered line.
+        let text = "אבג abc\u{05B0}.";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let debug = bidi_info.debug_visual(para, para.range.clone());
+        assert_eq!(debug, "[1].|[2]abc\u{05B0}|[1] גבא");
+        let stripped: String = debug.split('|').map(|run| &run[3..]).collect();
+        assert_eq!(stripped, bidi_info.reorder_line(para, para.range.clone()));
+    }
+
+    #[test]
+    fn test_reorder_line_chars() {
+        let text = "ab אב\u{05B0} 12 \u{202B}cd\u{202C}\nאבג";
+        let bidi_info = BidiInfo::new(text, None);
+        for para in &bidi_info.paragraphs {
+            let chars = bidi_info.reorder_line_chars(para, para.range.clone());
+            assert_eq!(
+                chars.iter().collect::<String>(),
+                bidi_info.reorder_line(para, para.range.clone())
+            );
+        }
+        let para = &bidi_info.paragraphs[1];
+        assert_eq!(bidi_info.reorder_line_chars(para, para.range.clone()), vec!['ג', 'ב', 'א']);
+    }
+
+    #[test]
+    fn test_visual_columns() {
+        let text = "ab אב 12 ";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let columns = bidi_info.visual_columns(para, para.range.clone());
+        assert_eq!(columns, vec![0, 1, 2, 7, 6, 5, 3, 4, 8]);
+
+        // The columns are a permutation, and index the re-ordered line.
+        let text = "אבג abc 123 \u{5d3}\u{5b0}\u{5bc}!\nxyz";
+        let bidi_info = BidiInfo::new(text, None);
+        for para in &bidi_info.paragraphs {
+            let columns = bidi_info.visual_columns(para, para.range.clone());
+            let mut sorted = columns.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, (0..columns.len()).collect::<Vec<usize>>());
+
+            let display: Vec<char> =
+                bidi_info.reorder_line(para, para.range.clone()).chars().collect();
+            for (c, &column) in text[para.range.clone()].chars().zip(&columns) {
+                assert_eq!(display[column], c);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reorder_paragraph() {
+        let text = "abc אבג  \nאבג abc.\nabc";
+        let bidi_info = BidiInfo::new(text, None);
+        for para in &bidi_info.paragraphs {
+            assert_eq!(
+                bidi_info.reorder_paragraph(para),
+                bidi_info.reorder_line(para, para.range.clone())
+            );
+        }
+        let paras: Vec<_> = bidi_info
+            .paragraphs
+            .iter()
+            .map(|para| bidi_info.reorder_paragraph(para))
+            .collect();
+        assert_eq!(paras, vec!["abc גבא  \n", "\n.abc גבא", "abc"]);
+
+        // L1 also applies to paragraphs that don't start at the beginning of the text: the
+        // whitespace before the tab is reset to the paragraph level.
+        let text = "a\nאבג \tדהו";
+        let bidi_info = BidiInfo::new(text, Some(LTR_LEVEL));
+        let para = &bidi_info.paragraphs[1];
+        assert_eq!(bidi_info.reorder_paragraph(para), "גבא \tוהד");
+        assert_eq!(
+            bidi_info.reordered_levels_per_char(para, para.range.clone())[2..],
+            Level::vec(&[1, 1, 1, 0, 0, 1, 1, 1])[..]
+        );
+    }
+
+    #[test]
+    fn test_runs_sorted_by_level() {
+        let text = "abc אבג 123 def";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let sorted = bidi_info.runs_sorted_by_level(para, para.range.clone());
+        assert_eq!(
+            sorted,
+            vec![
+                (Level::new(2).unwrap(), 11..14),
+                (RTL_LEVEL, 4..11),
+                (LTR_LEVEL, 0..4),
+                (LTR_LEVEL, 14..18),
+            ]
+        );
+        for pair in sorted.windows(2) {
+            assert!(pair[0].0 >= pair[1].0);
+        }
+    }
+
+    #[test]
+    fn test_to_debug_json() {
+        let text = "a \"\u{202B}\\\nאב";
+        let bidi_info = BidiInfo::new(text, None);
+        let json = bidi_info.to_debug_json();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"text":"a \"\u202B\\\nאב","#,
+                r#""paragraphs":[{"start":0,"end":8,"level":0},{"start":8,"end":12,"level":1}],"#,
+                r#""levels":[0,0,0,0,0,0,1,0,1,1,1,1],"#,
+                r#""classes":["L","WS","ON","RLE","RLE","RLE","ON","B","R","R","R","R"]}"#,
+            )
+        );
+
+        // One level per byte.
+        let levels_start = json.find("\"levels\":[").unwrap() + "\"levels\":[".len();
+        let levels_end = levels_start + json[levels_start..].find(']').unwrap();
+        assert_eq!(json[levels_start..levels_end].split(',').count(), bidi_info.levels.len());
+
+        assert_eq!(
+            BidiInfo::new("", None).to_debug_json(),
+            r#"{"text":"","paragraphs":[],"levels":[],"classes":[]}"#
+        );
+    }
+
+    #[test]
+    fn test_mirror_positions() {
+        // An RTL line with brackets around Hebrew and Latin words.  The "1" follows "abc", so it
+        // is LTR (rule W7), along with the brackets between them.
+        let text = "אב (גד) [abc] <1>";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert!(para.level.is_rtl());
+        assert_eq!(
+            bidi_info.mirror_positions(para, para.range.clone()),
+            vec![(5, ')'), (10, '('), (12, ']'), (20, '<')]
+        );
+        for (i, _) in bidi_info.mirror_positions(para, para.range.clone()) {
+            assert!(bidi_info.levels[i].is_rtl());
+        }
+
+        // Only the line is considered.
+        assert_eq!(bidi_info.mirror_positions(para, 0..11), vec![(5, ')'), (10, '(')]);
+
+        // Nothing is mirrored in LTR text.
+        let bidi_info = BidiInfo::new("a (b) [c]", None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.mirror_positions(para, para.range.clone()), vec![]);
+    }
+
+    #[test]
+    fn test_default_ignorable_runs() {
+        // A ZWSP is removed by rule X9, so it doesn't split the run it is in.
+        for &(text, level) in &[
+            ("ab\u{200B}cd", LTR_LEVEL),
+            ("אב\u{200B}גד", RTL_LEVEL),
+            ("\u{200B}ab\u{2060}cd\u{FEFF}", LTR_LEVEL),
+        ] {
+            assert!(text.chars().any(is_default_ignorable_bn));
+            let bidi_info = BidiInfo::new(text, None);
+            let para = &bidi_info.paragraphs[0];
+            assert_eq!(bidi_info.visual_runs(para, para.range.clone()).1, vec![0..text.len()]);
+            assert!(bidi_info.levels.iter().all(|&l| l == level), "{:?}", text);
+        }
+
+        // Neither does a ZWSP between a letter and a number.
+        let text = "אב\u{200B}12";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.visual_runs(para, para.range.clone()).1, vec![7..9, 0..7]);
+    }
+
+    #[test]
+    fn test_run_at() {
+        let text = "abc אבג 123 def";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let line = para.range.clone();
+        assert_eq!(bidi_info.run_at(para, line.clone(), 0), Some((LTR_LEVEL, 0..4)));
+        assert_eq!(bidi_info.run_at(para, line.clone(), 6), Some((RTL_LEVEL, 4..11)));
+        let number_level = Level::new(2).unwrap();
+        assert_eq!(bidi_info.run_at(para, line.clone(), 12), Some((number_level, 11..14)));
+        assert_eq!(bidi_info.run_at(para, line.clone(), text.len()), None);
+
+        // Only the runs within the line are considered.
+        assert_eq!(bidi_info.run_at(para, 4..10, 6), Some((RTL_LEVEL, 4..10)));
+        assert_eq!(bidi_info.run_at(para, 4..10, 2), None);
+    }
+
+    #[test]
+    fn test_apply_l2_reverse() {
+        let levels = Level::vec(&[0, 1, 1, 2, 2, 3, 2, 1, 0, 4, 4, 0]);
+        let mut items: Vec<u32> = (0..levels.len() as u32).collect();
+        apply_l2_reverse(&levels, &mut items);
+        assert_eq!(items, vec![0, 7, 3, 4, 5, 6, 2, 1, 8, 9, 10, 11]);
+
+        // Same as the permutation of `reorder_runs()`.
+        let runs: Vec<(Level, ())> = levels.iter().map(|&level| (level, ())).collect();
+        let expected: Vec<u32> = reorder_runs(&runs).into_iter().map(|i| i as u32).collect();
+        assert_eq!(items, expected);
+
+        let levels = Level::vec(&[1, 1, 1]);
+        let mut items = vec!['a', 'b', 'c'];
+        apply_l2_reverse(&levels, &mut items);
+        assert_eq!(items, vec!['c', 'b', 'a']);
+
+        let mut items: Vec<u32> = vec![];
+        apply_l2_reverse(&[], &mut items);
+        assert_eq!(items, vec![]);
+    }
+
+    #[test]
+    fn test_reorder_runs() {
+        let runs: Vec<(Level, &str)> = Level::vec(&[0, 1, 2, 1, 0])
+            .into_iter()
+            .zip(vec!["a", "b", "c", "d", "e"])
+            .collect();
+        assert_eq!(reorder_runs(&runs), vec![0, 3, 2, 1, 4]);
+
+        let runs: Vec<(Level, ())> =
+            Level::vec(&[1, 2, 2, 3, 0]).into_iter().map(|l| (l, ())).collect();
+        assert_eq!(reorder_runs(&runs), vec![1, 2, 3, 0, 4]);
+
+        assert_eq!(reorder_runs::<()>(&[]), vec![]);
+        assert_eq!(reorder_runs(&[(LTR_LEVEL, ()), (LTR_LEVEL, ())]), vec![0, 1]);
+
+        // Matches the order of `visual_runs()`.
+        let bidi_info = BidiInfo::new("abc אבג 123 def", None);
+        let para = &bidi_info.paragraphs[0];
+        let (levels, runs) = bidi_info.visual_runs(para, para.range.clone());
+        let mut logical = runs.clone();
+        logical.sort_by_key(|run| run.start);
+        let pairs: Vec<(Level, LevelRun)> =
+            logical.iter().map(|run| (levels[run.start], run.clone())).collect();
+        let visual: Vec<LevelRun> =
+            reorder_runs(&pairs).into_iter().map(|i| logical[i].clone()).collect();
+        assert_eq!(visual, runs);
+    }
+
+    #[test]
+    fn test_reorder_all_paragraphs() {
+        let text = "abc אבג\nאבג abc\n123 def";
+        let bidi_info = BidiInfo::new(text, None);
+        let reordered = bidi_info.reorder_all_paragraphs();
+        // The paragraph separator of an RTL paragraph is displayed on the left.
+        assert_eq!(reordered, vec!["abc גבא\n", "\nabc גבא", "123 def"]);
+        assert_eq!(reordered.len(), bidi_info.paragraphs.len());
+        for (para, line) in bidi_info.paragraphs.iter().zip(&reordered) {
+            assert_eq!(*line, bidi_info.reorder_line(para, para.range.clone()));
+        }
+
+        assert_eq!(BidiInfo::new("", None).reorder_all_paragraphs(), Vec::<Cow<'_, str>>::new());
+    }
+
+    #[test]
+    fn test_reordered_lines_iter() {
+        let text = "abc אבג\u{2028}אבג abc\u{000C}123 def\n";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(para.range, 0..text.len());
+        let lines: Vec<_> = bidi_info.reordered_lines_iter(para).collect();
+        assert_eq!(
+            lines,
+            vec![
+                bidi_info.reorder_line(para, 0..13),
+                bidi_info.reorder_line(para, 13..24),
+                bidi_info.reorder_line(para, 24..32),
+            ]
+        );
+        assert_eq!(lines, vec!["abc גבא\u{2028}", "גבא abc\u{000C}", "123 def\n"]);
+
+        // Lines are only re-ordered on demand.
+        let mut lines = bidi_info.reordered_lines_iter(para);
+        assert_eq!(lines.next().unwrap(), "abc גבא\u{2028}");
+
+        let bidi_info = BidiInfo::new("abc", None);
+        let lines: Vec<_> = bidi_info.reordered_lines_iter(&bidi_info.paragraphs[0]).collect();
+        assert_eq!(lines, vec!["abc"]);
+    }
+
+    #[test]
+    fn test_line_and_paragraph_separators() {
+        // U+2028 LINE SEPARATOR ends a line, but not the paragraph.
+        let text = "abc אבג\u{2028}def";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.original_classes[10], WS);
+        assert_eq!(bidi_info.paragraphs.len(), 1);
+        let para = &bidi_info.paragraphs[0];
+        let lines: Vec<_> = bidi_info.reordered_lines_iter(para).collect();
+        assert_eq!(lines, vec!["abc גבא\u{2028}", "def"]);
+        // Rule L1 resets the separator to the paragraph level at the end of its line.
+        assert_eq!(bidi_info.reordered_levels(para, 0..13)[10], LTR_LEVEL);
+
+        // U+2029 PARAGRAPH SEPARATOR ends the paragraph.
+        let text = "abc אבג\u{2029}def";
+        let bidi_info = BidiInfo::new(text, None);
+        assert_eq!(bidi_info.original_classes[10], B);
+        assert_eq!(bidi_info.paragraphs.len(), 2);
+        let lines: Vec<Vec<_>> = bidi_info
+            .paragraphs
+            .iter()
+            .map(|para| bidi_info.reordered_lines_iter(para).collect())
+            .collect();
+        assert_eq!(lines, vec![vec!["abc גבא\u{2029}"], vec!["def"]]);
+    }
+
+    #[test]
+    fn test_reorder_lines() {
+        let text = "abc אבג def \u{2067}גדה xyz\u{2069} 123 וזח.";
+        for &level in &[None, Some(RTL_LEVEL)] {
+            let bidi_info = BidiInfo::new(text, level);
+            let para = &bidi_info.paragraphs[0];
+            let boundaries: Vec<usize> = text
+                .char_indices()
+                .filter(|&(_, c)| c == ' ')
+                .map(|(i, _)| i + 1)
+                .collect();
+            let mut lines = vec![];
+            let mut start = 0;
+            for &end in boundaries.iter().chain(Some(&text.len())) {
+                lines.push(start..end);
+                start = end;
+            }
+            lines.push(0..text.len());
+
+            let reordered = bidi_info.reorder_lines(para, &lines);
+            assert_eq!(reordered.len(), lines.len());
+            for (line, result) in lines.iter().zip(&reordered) {
+                assert_eq!(*result, bidi_info.reorder_line(para, line.clone()), "{:?}", line);
+            }
+        }
+
+        // An empty line in the middle of a paragraph
+        let bidi_info = BidiInfo::new("abc אבג", None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.reorder_line(para, 3..3), "");
+        assert_eq!(bidi_info.reorder_lines(para, &[0..3, 3..3, 3..10]), vec!["abc", "", " גבא"]);
+        assert_eq!(bidi_info.visual_runs(para, 3..3), (bidi_info.levels.clone(), vec![]));
+    }
+
+    #[test]
+    fn test_is_whole_text_identity() {
+        assert!(BidiInfo::new("abc\ndef 123\n", None).is_whole_text_identity());
+        assert!(BidiInfo::new("", None).is_whole_text_identity());
+
+        let bidi_info = BidiInfo::new("abc\nאבג\ndef", None);
+        assert!(bidi_info.paragraph_is_identity(&bidi_info.paragraphs[0]));
+        assert!(!bidi_info.paragraph_is_identity(&bidi_info.paragraphs[1]));
+        assert!(!bidi_info.is_whole_text_identity());
     }
 
     #[test]
-    fn test_process_text() {
-        let text = "abc123";
-        assert_eq!(
-            BidiInfo::new(text, Some(LTR_LEVEL)),
-            BidiInfo {
-                text,
-                levels: Level::vec(&[0, 0, 0, 0, 0, 0]),
-                original_classes: vec![L, L, L, EN, EN, EN],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..6,
-                        level: LTR_LEVEL,
-                    },
-                ],
+    fn test_paragraph_at() {
+        let text = "abc\nאבג\n\ndef";
+        let bidi_info = BidiInfo::new(text, None);
+        let ranges: Vec<_> = bidi_info.paragraphs.iter().map(|p| p.range.clone()).collect();
+        assert_eq!(ranges, vec![0..4, 4..11, 11..12, 12..15]);
+        for (index, range) in ranges.iter().enumerate() {
+            for i in range.clone() {
+                assert_eq!(bidi_info.paragraph_at(i), index, "{}", i);
             }
-        );
+        }
+        // Boundaries belong to the following paragraph.
+        assert_eq!(bidi_info.paragraph_at(3), 0);
+        assert_eq!(bidi_info.paragraph_at(4), 1);
+        assert_eq!(bidi_info.paragraph_at(11), 2);
+        assert_eq!(bidi_info.paragraph_at(12), 3);
+        assert_eq!(bidi_info.paragraph_at(text.len()), 3);
 
-        let text = "abc אבג";
+        let bidi_info = BidiInfo::new("a", None);
+        assert_eq!(bidi_info.paragraph_at(0), 0);
+        assert_eq!(bidi_info.paragraph_at(1), 0);
+    }
+
+    #[test]
+    fn test_paragraph_is_identity() {
+        let text = "abc def\nabc אבג def\nאבג";
+        let bidi_info = BidiInfo::new(text, None);
+        let paras = &bidi_info.paragraphs;
+        assert!(bidi_info.paragraph_is_identity(&paras[0]));
+        assert!(!bidi_info.paragraph_is_identity(&paras[1]));
+        assert!(!bidi_info.paragraph_is_identity(&paras[2]));
+        assert_eq!(bidi_info.reorder_paragraph(&paras[0]), "abc def\n");
+
+        // LTR text within an RTL paragraph is at an even level, unless rule L1 resets its
+        // trailing whitespace to the paragraph level.
+        let bidi_info = BidiInfo::new("abc", Some(RTL_LEVEL));
+        assert!(bidi_info.paragraph_is_identity(&bidi_info.paragraphs[0]));
+        let bidi_info = BidiInfo::new("abc ", Some(RTL_LEVEL));
+        assert!(!bidi_info.paragraph_is_identity(&bidi_info.paragraphs[0]));
+
+        // Same as checking the levels of the paragraph after rule L1.
+        for &(text, level) in &[
+            ("\u{200B}", Some(RTL_LEVEL)),
+            ("\u{200B}", None),
+            ("abc \u{202B}def\u{202C}\u{200B}", None),
+            ("abc\u{200B} \t", Some(RTL_LEVEL)),
+            ("\u{2067}abc\u{2069} def", Some(RTL_LEVEL)),
+        ] {
+            let bidi_info = BidiInfo::new(text, level);
+            let para = &bidi_info.paragraphs[0];
+            let levels = bidi_info.reordered_levels(para, para.range.clone());
+            assert_eq!(
+                bidi_info.paragraph_is_identity(para),
+                levels.iter().all(|level| level.is_ltr()),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_has_removed_chars() {
+        let text = "abc \u{202B}אבג\u{202C} def\nabc אבג def\na\u{00AD}b";
+        let bidi_info = BidiInfo::new(text, None);
+        let paras = &bidi_info.paragraphs;
+        assert!(bidi_info.has_removed_chars(&paras[0]));
+        assert!(!bidi_info.has_removed_chars(&paras[1]));
+        assert!(bidi_info.has_removed_chars(&paras[2]));
+        for para in paras.iter().filter(|para| !bidi_info.has_removed_chars(para)) {
+            assert_eq!(
+                bidi_info.reorder_line_with_removed(para, para.range.clone(), RemovedChars::Drop),
+                bidi_info.reorder_line(para, para.range.clone())
+            );
+        }
+
+        // Isolates are not removed by X9.
+        let bidi_info = BidiInfo::new("abc \u{2067}אבג\u{2069}", None);
+        assert!(!bidi_info.has_removed_chars(&bidi_info.paragraphs[0]));
+    }
+
+    #[test]
+    fn test_digit_shaping() {
+        use DigitShape::{ArabicIndic, European};
+
+        // European digits after Arabic letters, and after Latin letters
+        let text = "\u{0627}\u{0644} 12 ab 34";
+        let bidi_info = BidiInfo::new(text, None);
         assert_eq!(
-            BidiInfo::new(text, Some(LTR_LEVEL)),
-            BidiInfo {
-                text,
-                levels: Level::vec(&[0, 0, 0, 0, 1, 1, 1, 1, 1, 1]),
-                original_classes: vec![L, L, L, WS, R, R, R, R, R, R],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..10,
-                        level: LTR_LEVEL,
-                    },
-                ],
-            }
+            bidi_info.digit_shaping(&bidi_info.paragraphs[0]),
+            vec![
+                None,
+                None,
+                None,
+                Some(ArabicIndic),
+                Some(ArabicIndic),
+                None,
+                None,
+                None,
+                None,
+                Some(European),
+                Some(European),
+            ]
         );
+
+        // Arabic numbers, Hebrew letters, and isolates, which W2 doesn't look across.
+        let text = "\u{0661} א 5 \u{0627}\u{2066}6\u{2069}7\n8";
+        let bidi_info = BidiInfo::new(text, None);
+        let shapes: Vec<Vec<DigitShape>> = bidi_info
+            .paragraphs
+            .iter()
+            .map(|para| bidi_info.digit_shaping(para).into_iter().flatten().collect())
+            .collect();
         assert_eq!(
-            BidiInfo::new(text, Some(RTL_LEVEL)),
-            BidiInfo {
-                text,
-                levels: Level::vec(&[2, 2, 2, 1, 1, 1, 1, 1, 1, 1]),
-                original_classes: vec![L, L, L, WS, R, R, R, R, R, R],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..10,
-                        level: RTL_LEVEL,
-                    },
-                ],
-            }
+            shapes,
+            vec![
+                vec![ArabicIndic, European, European, ArabicIndic],
+                vec![European],
+            ]
         );
+    }
 
-        let text = "אבג abc";
+    #[test]
+    fn test_levels_without_removed() {
+        let text = "a\u{202B}bc אב\u{202C}d\u{00AD}\nא\u{202A}b";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let (stripped, levels) = bidi_info.levels_without_removed(para);
+        assert_eq!(stripped, "abc אבd\n");
+        assert_eq!(levels.len(), stripped.len());
+        assert!(!stripped.chars().any(|c| matches!(c, '\u{202B}' | '\u{202C}' | '\u{00AD}')));
+        assert_eq!(levels, Level::vec(&[0, 2, 2, 1, 1, 1, 1, 1, 0, 0]));
+
+        // Only the given paragraph is included.
+        let para = &bidi_info.paragraphs[1];
+        let (stripped, levels) = bidi_info.levels_without_removed(para);
+        assert_eq!(stripped, "אb");
+        assert_eq!(levels, Level::vec(&[1, 1, 2]));
+
+        let bidi_info = BidiInfo::new("abc אב", None);
+        let para = &bidi_info.paragraphs[0];
         assert_eq!(
-            BidiInfo::new(text, Some(LTR_LEVEL)),
-            BidiInfo {
-                text,
-                levels: Level::vec(&[1, 1, 1, 1, 1, 1, 0, 0, 0, 0]),
-                original_classes: vec![R, R, R, R, R, R, WS, L, L, L],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..10,
-                        level: LTR_LEVEL,
-                    },
-                ],
-            }
+            bidi_info.levels_without_removed(para),
+            ("abc אב".to_owned(), bidi_info.levels.clone())
         );
+    }
+
+    #[test]
+    fn test_edit_is_local() {
+        let text = "abc אבג\ndef";
+        let bidi_info = BidiInfo::new(text, None);
+        assert!(bidi_info.edit_is_local(3, " "));
+        assert!(bidi_info.edit_is_local(6, "!? "));
+        assert!(bidi_info.edit_is_local(text.len(), " "));
+        assert!(!bidi_info.edit_is_local(3, "\u{2067}"));
+        assert!(!bidi_info.edit_is_local(3, " x"));
+        assert!(!bidi_info.edit_is_local(3, "1"));
+        assert!(!bidi_info.edit_is_local(3, "\n"));
+
+        // After a final paragraph separator, an insertion starts a new paragraph.
+        let bidi_info = BidiInfo::new("abc\n", None);
+        assert!(bidi_info.edit_is_local(3, " "));
+        assert!(!bidi_info.edit_is_local(4, " "));
+        assert!(!BidiInfo::new("", None).edit_is_local(0, " "));
+
+        // Numbers and their separators are resolved together with their neighbors.
+        let bidi_info = BidiInfo::new("abc 1,2 \u{202A}%", None);
+        assert!(bidi_info.edit_is_local(3, " "));
+        assert!(!bidi_info.edit_is_local(4, " "));
+        assert!(!bidi_info.edit_is_local(5, " "));
+        assert!(!bidi_info.edit_is_local(7, " "));
+        assert!(!bidi_info.edit_is_local(8, " "));
+        assert!(!BidiInfo::new("\u{05D0}\u{05B0}", None).edit_is_local(2, " "));
+
+        // The resolved levels of the text around a local edit don't change.
+        let edited = "abc  אבג\ndef";
+        let after = BidiInfo::new(edited, None);
+        assert_eq!(after.levels[..3], BidiInfo::new(text, None).levels[..3]);
+        assert_eq!(after.levels[4..], BidiInfo::new(text, None).levels[3..]);
+    }
+
+    #[test]
+    fn test_level_bounds() {
+        let text = "abc אבג 123";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
         assert_eq!(
-            BidiInfo::new(text, None),
-            BidiInfo {
-                text,
-                levels: Level::vec(&[1, 1, 1, 1, 1, 1, 1, 2, 2, 2]),
-                original_classes: vec![R, R, R, R, R, R, WS, L, L, L],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..10,
-                        level: RTL_LEVEL,
-                    },
-                ],
-            }
+            bidi_info.level_bounds(para, para.range.clone()),
+            (LTR_LEVEL, Level::new(2).unwrap())
         );
-
-        let text = "غ2ظ א2ג";
         assert_eq!(
-            BidiInfo::new(text, Some(LTR_LEVEL)),
-            BidiInfo {
-                text,
-                levels: Level::vec(&[1, 1, 2, 1, 1, 1, 1, 1, 2, 1, 1]),
-                original_classes: vec![AL, AL, EN, AL, AL, WS, R, R, EN, R, R],
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..11,
-                        level: LTR_LEVEL,
-                    },
-                ],
-            }
+            bidi_info.level_bounds(para, 4..para.range.end),
+            (RTL_LEVEL, Level::new(2).unwrap())
         );
+        assert_eq!(bidi_info.level_bounds(para, 0..3), (LTR_LEVEL, LTR_LEVEL));
+        assert_eq!(bidi_info.level_bounds(para, 3..3), (LTR_LEVEL, LTR_LEVEL));
 
-        let text = "a א.\nג";
+        // Rule L1 resets the trailing whitespace to the paragraph level.
+        let bidi_info = BidiInfo::new("אבג ", Some(Level::new(2).unwrap()));
+        let para = &bidi_info.paragraphs[0];
         assert_eq!(
-            BidiInfo::new(text, None),
-            BidiInfo {
-                text,
-                original_classes: vec![L, WS, R, R, CS, B, R, R],
-                levels: Level::vec(&[0, 0, 1, 1, 0, 0, 1, 1]),
-                paragraphs: vec![
-                    ParagraphInfo {
-                        range: 0..6,
-                        level: LTR_LEVEL,
-                    },
-                    ParagraphInfo {
-                        range: 6..8,
-                        level: RTL_LEVEL,
-                    },
-                ],
-            }
+            bidi_info.level_bounds(para, para.range.clone()),
+            (Level::new(2).unwrap(), Level::new(3).unwrap())
         );
-
-        /// BidiTest:69635 (AL ET EN)
-        let bidi_info = BidiInfo::new("\u{060B}\u{20CF}\u{06F9}", None);
-        assert_eq!(bidi_info.original_classes, vec![AL, AL, ET, ET, ET, EN, EN]);
     }
 
     #[test]
-    fn test_bidi_info_has_rtl() {
-        // ASCII only
-        assert_eq!(BidiInfo::new("123", None).has_rtl(), false);
-        assert_eq!(BidiInfo::new("123", Some(LTR_LEVEL)).has_rtl(), false);
-        assert_eq!(BidiInfo::new("123", Some(RTL_LEVEL)).has_rtl(), false);
-        assert_eq!(BidiInfo::new("abc", None).has_rtl(), false);
-        assert_eq!(BidiInfo::new("abc", Some(LTR_LEVEL)).has_rtl(), false);
-        assert_eq!(BidiInfo::new("abc", Some(RTL_LEVEL)).has_rtl(), false);
-        assert_eq!(BidiInfo::new("abc 123", None).has_rtl(), false);
-        assert_eq!(BidiInfo::new("abc\n123", None).has_rtl(), false);
+    fn test_trailing_whitespace_range() {
+        let text = "abc אבג   \nabc";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.trailing_whitespace_range(para, para.range.clone()), 10..14);
+        assert_eq!(bidi_info.trailing_whitespace_range(para, 0..12), 10..12);
+        assert_eq!(bidi_info.trailing_whitespace_range(para, 0..10), 10..10);
+        assert_eq!(bidi_info.trailing_whitespace_range(para, 0..3), 3..3);
+        assert_eq!(bidi_info.trailing_whitespace_range(para, 0..4), 3..4);
 
-        // With Hebrew
-        assert_eq!(BidiInfo::new("אבּג", None).has_rtl(), true);
-        assert_eq!(BidiInfo::new("אבּג", Some(LTR_LEVEL)).has_rtl(), true);
-        assert_eq!(BidiInfo::new("אבּג", Some(RTL_LEVEL)).has_rtl(), true);
-        assert_eq!(BidiInfo::new("abc אבּג", None).has_rtl(), true);
-        assert_eq!(BidiInfo::new("abc\nאבּג", None).has_rtl(), true);
-        assert_eq!(BidiInfo::new("אבּג abc", None).has_rtl(), true);
-        assert_eq!(BidiInfo::new("אבּג\nabc", None).has_rtl(), true);
-        assert_eq!(BidiInfo::new("אבּג 123", None).has_rtl(), true);
-        assert_eq!(BidiInfo::new("אבּג\n123", None).has_rtl(), true);
-    }
+        // The reset whitespace is at the paragraph level after L1.
+        let (levels, _) = bidi_info.visual_runs(para, 0..12);
+        assert_eq!(levels[10..12], Level::vec(&[0, 0])[..]);
 
-    fn reorder_paras(text: &str) -> Vec<Cow<str>> {
-        let bidi_info = BidiInfo::new(text, None);
-        bidi_info
-            .paragraphs
-            .iter()
-            .map(|para| bidi_info.reorder_line(para, para.range.clone()))
-            .collect()
+        let para = &bidi_info.paragraphs[1];
+        assert_eq!(bidi_info.trailing_whitespace_range(para, para.range.clone()), 17..17);
+
+        // Removed characters are only included after some whitespace.
+        let bidi_info = BidiInfo::new("a\u{200B}\u{202C} \u{202C}", None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.trailing_whitespace_range(para, para.range.clone()), 7..11);
+        assert_eq!(bidi_info.trailing_whitespace_range(para, 0..7), 7..7);
     }
 
     #[test]
-    fn test_reorder_line() {
-        /// Bidi_Class: L L L B L L L B L L L
-        assert_eq!(
-            reorder_paras("abc\ndef\nghi"),
-            vec!["abc\n", "def\n", "ghi"]
-        );
+    fn test_level_run_count() {
+        let texts = [
+            "abc",
+            "אבג",
+            "abc אבג def",
+            "אבג abc 123 ",
+            "abc\tאבג \u{2067}abc\u{2069}  ",
+            "a\u{202B}bc\u{202C}d",
+            "\u{202B}abc\u{202C}",
+            "\u{202B}",
+        ];
+        for text in &texts {
+            for &level in &[None, Some(RTL_LEVEL)] {
+                let bidi_info = BidiInfo::new(text, level);
+                let para = &bidi_info.paragraphs[0];
+                let ends = text.char_indices().map(|(i, _)| i).skip(1).chain(Some(text.len()));
+                for end in ends {
+                    assert_eq!(
+                        bidi_info.level_run_count(para, 0..end),
+                        bidi_info.visual_runs(para, 0..end).1.len(),
+                        "{:?} {:?} 0..{}",
+                        text,
+                        level,
+                        end
+                    );
+                }
+            }
+        }
 
-        /// Bidi_Class: L L EN B L L EN B L L EN
-        assert_eq!(
-            reorder_paras("ab1\nde2\ngh3"),
-            vec!["ab1\n", "de2\n", "gh3"]
-        );
+        let bidi_info = BidiInfo::new("abc", None);
+        assert_eq!(bidi_info.level_run_count(&bidi_info.paragraphs[0], 1..1), 0);
+    }
 
-        /// Bidi_Class: L L L B AL AL AL
-        assert_eq!(reorder_paras("abc\nابج"), vec!["abc\n", "جبا"]);
+    #[test]
+    fn test_ellipsis_side() {
+        // RTL line in an RTL paragraph
+        let bidi_info = BidiInfo::new("אבג דהו", None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.ellipsis_side(para, 0..7), VisualDirection::Left);
 
-        /// Bidi_Class: AL AL AL B L L L
-        assert_eq!(reorder_paras("ابج\nabc"), vec!["\nجبا", "abc"]);
+        // LTR line in an LTR paragraph
+        let bidi_info = BidiInfo::new("abc def", None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.ellipsis_side(para, 0..4), VisualDirection::Right);
 
-        assert_eq!(reorder_paras("1.-2"), vec!["1.-2"]);
-        assert_eq!(reorder_paras("1-.2"), vec!["1-.2"]);
-        assert_eq!(reorder_paras("abc אבג"), vec!["abc גבא"]);
+        // Mixed lines use the paragraph direction, and trailing whitespace is ignored.
+        let bidi_info = BidiInfo::new("abc אבג ", None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.ellipsis_side(para, para.range.clone()), VisualDirection::Right);
+        assert_eq!(bidi_info.ellipsis_side(para, 4..11), VisualDirection::Left);
+        assert_eq!(bidi_info.ellipsis_side(para, 3..4), VisualDirection::Right);
+    }
 
-        // Numbers being weak LTR characters, cannot reorder strong RTL
-        assert_eq!(reorder_paras("123 אבג"), vec!["גבא 123"]);
+    #[test]
+    fn test_visual_runs_across_removed_chars() {
+        // LRE and PDF don't split the LTR text around them.
+        let text = "a\u{202A}\u{202C}b";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.visual_runs(para, para.range.clone()).1, vec![0..8]);
+        assert_eq!(bidi_info.visual_runs(para, 1..8).1, vec![1..8]);
 
-        assert_eq!(reorder_paras("abc\u{202A}def"), vec!["abc\u{202A}def"]);
+        // Removed characters at the start of a line join the run that follows them, even if the
+        // character before the line has another level.
+        let text = "\u{05D0}\u{202A}b\u{202C}c";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.visual_runs(para, para.range.clone()).1, vec![5..10, 0..5]);
+        assert_eq!(bidi_info.visual_runs(para, 2..10).1, vec![2..10]);
 
+        let text = "\u{202B}\u{202C}\u{05D0}\u{05D1} abc";
+        let bidi_info = BidiInfo::new(text, Some(LTR_LEVEL));
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(bidi_info.visual_runs(para, para.range.clone()).1, vec![0..10, 10..14]);
         assert_eq!(
-            reorder_paras("abc\u{202A}def\u{202C}ghi"),
-            vec!["abc\u{202A}def\u{202C}ghi"]
+            bidi_info.reordered_levels(para, para.range.clone())[..10],
+            Level::vec(&[1; 10])[..]
         );
+    }
 
+    #[test]
+    fn test_visual_runs_iter() {
+        let text = "abc אבג def";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let runs: Vec<Run> = bidi_info.visual_runs_iter(para, para.range.clone()).collect();
         assert_eq!(
-            reorder_paras("abc\u{2066}def\u{2069}ghi"),
-            vec!["abc\u{2066}def\u{2069}ghi"]
+            runs,
+            vec![
+                Run::new(0..4, LTR_LEVEL),
+                Run::new(4..10, RTL_LEVEL),
+                Run::new(10..14, LTR_LEVEL),
+            ]
         );
+        assert_eq!(
+            runs.iter().map(|run| run.range.clone()).collect::<Vec<_>>(),
+            bidi_info.visual_runs(para, para.range.clone()).1
+        );
+        assert!(runs.iter().all(|run| run.orientation == Orientation::Unspecified));
+
+        let run = runs[0].clone().with_orientation(Orientation::Sideways);
+        assert_eq!(run.orientation, Orientation::Sideways);
+        assert_eq!(run.range, 0..4);
+    }
 
-        // Testing for RLE Character
+    #[test]
+    fn test_run_mirroring() {
+        let text = "אב (גד) [abc]";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let line = para.range.clone();
+        let runs: Vec<Run> = bidi_info.visual_runs_iter(para, line.clone()).collect();
         assert_eq!(
-            reorder_paras("\u{202B}abc אבג\u{202C}"),
-            vec!["\u{202B}\u{202C}גבא abc"]
+            runs,
+            vec![
+                Run::new(16..17, RTL_LEVEL),
+                Run::new(13..16, Level::new(2).unwrap()),
+                Run::new(0..13, RTL_LEVEL),
+            ]
         );
 
-        // Testing neutral characters
-        assert_eq!(reorder_paras("אבג? אבג"), vec!["גבא ?גבא"]);
+        // The brackets of the RTL runs are mirrored.
+        assert!(runs[2].needs_mirroring());
+        assert_eq!(runs[2].mirror_substitutions(text), vec![(5, ')'), (10, '('), (12, ']')]);
+        assert!(runs[0].needs_mirroring());
+        assert_eq!(runs[0].mirror_substitutions(text), vec![(16, '[')]);
+        assert!(!runs[1].needs_mirroring());
+        assert_eq!(runs[1].mirror_substitutions(text), vec![]);
+        assert_eq!(Run::new(12..17, LTR_LEVEL).mirror_substitutions(text), vec![]);
 
-        // Testing neutral characters with special case
-        assert_eq!(reorder_paras("A אבג?"), vec!["A גבא?"]);
+        // Together, the runs report the same substitutions as the line.
+        let mut substitutions: Vec<(usize, char)> =
+            runs.iter().flat_map(|run| run.mirror_substitutions(text)).collect();
+        substitutions.sort_unstable();
+        assert_eq!(substitutions, bidi_info.mirror_positions(para, line));
+    }
 
-        // Testing neutral characters with Implicit RTL Marker
+    #[test]
+    fn test_runs_with_breaks() {
+        // A uniform level line split at two attribute boundaries.
+        let text = "abcdefghi";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
         assert_eq!(
-            reorder_paras("A אבג?\u{200F}"),
-            vec!["A \u{200F}?גבא"]
+            bidi_info.runs_with_breaks(para, para.range.clone(), &[6, 3]),
+            vec![
+                Run::new(0..3, LTR_LEVEL),
+                Run::new(3..6, LTR_LEVEL),
+                Run::new(6..9, LTR_LEVEL),
+            ]
         );
-        assert_eq!(reorder_paras("אבג abc"), vec!["abc גבא"]);
         assert_eq!(
-            reorder_paras("abc\u{2067}.-\u{2069}ghi"),
-            vec!["abc\u{2067}-.\u{2069}ghi"]
+            bidi_info.runs_with_breaks(para, para.range.clone(), &[0, 9, 12]),
+            vec![Run::new(0..9, LTR_LEVEL)]
         );
 
+        // The pieces of an RTL run are displayed from right to left.
+        let text = "abc אבג def";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
         assert_eq!(
-            reorder_paras("Hello, \u{2068}\u{202E}world\u{202C}\u{2069}!"),
-            vec!["Hello, \u{2068}\u{202E}\u{202C}dlrow\u{2069}!"]
+            bidi_info.runs_with_breaks(para, para.range.clone(), &[2, 6, 6, 10]),
+            vec![
+                Run::new(0..2, LTR_LEVEL),
+                Run::new(2..4, LTR_LEVEL),
+                Run::new(6..10, RTL_LEVEL),
+                Run::new(4..6, RTL_LEVEL),
+                Run::new(10..14, LTR_LEVEL),
+            ]
         );
+    }
 
-        // With mirrorable characters in RTL run
-        assert_eq!(reorder_paras("א(ב)ג."), vec![".ג)ב(א"]);
+    #[test]
+    fn test_runs_logical_and_visual() {
+        let text = "abc אבג 123 def";
+        let bidi_info = BidiInfo::new(text, Some(RTL_LEVEL));
+        let para = &bidi_info.paragraphs[0];
+        let (runs, visual) = bidi_info.runs_logical_and_visual(para, para.range.clone());
+
+        let starts: Vec<usize> = runs.iter().map(|run| run.range.start).collect();
+        let mut sorted = starts.clone();
+        sorted.sort();
+        assert_eq!(starts, sorted);
+        assert_eq!(runs[0], Run::new(0..3, Level::new(2).unwrap()));
+
+        let mut indices = visual.clone();
+        indices.sort();
+        assert_eq!(indices, (0..runs.len()).collect::<Vec<_>>());
 
-        // With mirrorable characters on level boundry
         assert_eq!(
-            reorder_paras("אב(גד[&ef].)gh"),
-            vec!["ef].)gh&[דג(בא"]
+            visual.iter().map(|&i| runs[i].clone()).collect::<Vec<_>>(),
+            bidi_info.visual_runs_iter(para, para.range.clone()).collect::<Vec<_>>()
         );
     }
 
@@ -809,6 +6082,52 @@ mod tests {
             .collect()
     }
 
+    #[test]
+    fn test_reordered_levels_with_separator() {
+        // An LTR paragraph that ends with an RTL run
+        let text = "abc אב\n";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        let line = para.range.clone();
+        let levels = bidi_info.reordered_levels_with_separator(
+            para,
+            line.clone(),
+            SeparatorLevel::ParagraphLevel,
+        );
+        assert_eq!(levels, bidi_info.reordered_levels(para, line.clone()));
+        assert_eq!(levels[8], LTR_LEVEL);
+        let levels =
+            bidi_info.reordered_levels_with_separator(para, line, SeparatorLevel::PrecedingRun);
+        assert_eq!(levels[..8], bidi_info.levels[..8]);
+        assert_eq!(levels[8], RTL_LEVEL);
+
+        // Whitespace before the separator is reset by L1, and removed characters are skipped.
+        let cases = [("ab אב \u{2029}", LTR_LEVEL), ("ab אב\u{202C}\u{2029}", RTL_LEVEL)];
+        for &(text, level) in &cases {
+            let bidi_info = BidiInfo::new(text, None);
+            let para = &bidi_info.paragraphs[0];
+            let levels = bidi_info.reordered_levels_with_separator(
+                para,
+                para.range.clone(),
+                SeparatorLevel::PrecedingRun,
+            );
+            assert_eq!(&levels[text.len() - 3..], &[level; 3]);
+        }
+
+        // No separator at the end of the line
+        let text = "abc אב";
+        let bidi_info = BidiInfo::new(text, None);
+        let para = &bidi_info.paragraphs[0];
+        assert_eq!(
+            bidi_info.reordered_levels_with_separator(
+                para,
+                para.range.clone(),
+                SeparatorLevel::PrecedingRun,
+            ),
+            bidi_info.reordered_levels(para, para.range.clone())
+        );
+    }
+
     #[test]
     fn test_reordered_levels() {
 
@@ -14,7 +14,7 @@
 use std::cmp::max;
 use std::ops::Range;
 
-use super::BidiClass;
+use super::{BidiClass, BidiClassSet, BidiInfo};
 use super::level::Level;
 
 use BidiClass::*;
@@ -26,13 +26,31 @@ pub type LevelRun = Range<usize>;
 
 
 /// Output of `isolating_run_sequences` (steps X9-X10)
-#[derive(Debug, PartialEq)]
+///
+/// See [`BidiInfo::isolating_run_sequences()`][1].
+///
+/// [1]: struct.BidiInfo.html#method.isolating_run_sequences
+///
+/// <http://www.unicode.org/reports/tr9/#BD13>
+#[derive(Clone, Debug, PartialEq)]
 pub struct IsolatingRunSequence {
+    /// The level runs of the sequence, in logical order.
     pub runs: Vec<LevelRun>,
     pub sos: BidiClass, // Start-of-sequence type.
     pub eos: BidiClass, // End-of-sequence type.
 }
 
+impl IsolatingRunSequence {
+    /// The text of the sequence, in logical order.
+    ///
+    /// The runs of the sequence are not contiguous if it contains isolates, so this concatenates
+    /// their text.  The runs must be byte ranges within `info.text`, like the ones returned by
+    /// `BidiInfo::isolating_run_sequences()`.
+    pub fn text(&self, info: &BidiInfo) -> String {
+        self.runs.iter().map(|run| &info.text[run.clone()]).collect()
+    }
+}
+
 
 /// Compute the set of isolating run sequences.
 ///
@@ -74,7 +92,7 @@ pub fn isolating_run_sequences(
 
         sequence.push(run);
 
-        if matches!(end_class, RLI | LRI | FSI) {
+        if BidiClassSet::ISOLATE_INITIATORS.contains(end_class) {
             // Resume this sequence after the isolate.
             stack.push(sequence);
         } else {
@@ -114,7 +132,8 @@ pub fn isolating_run_sequences(
             };
 
             // Get the level of the next non-removed char after the runs.
-            let succ_level = if matches!(original_classes[end_of_seq - 1], RLI | LRI | FSI) {
+            let last_class = original_classes[end_of_seq - 1];
+            let succ_level = if BidiClassSet::ISOLATE_INITIATORS.contains(last_class) {
                 para_level
             } else {
                 match original_classes[end_of_seq..].iter().position(
@@ -162,9 +181,13 @@ fn level_runs(levels: &[Level], original_classes: &[BidiClass]) -> Vec<LevelRun>
 
 /// Should this character be ignored in steps after X9?
 ///
+/// The implicit directional marks LRM, RLM and ALM are not removed: they are real characters of
+/// class `L`, `R` and `AL` in the class array, and take part in the weak and neutral rules like
+/// any other strong character.
+///
 /// <http://www.unicode.org/reports/tr9/#X9>
 pub fn removed_by_x9(class: BidiClass) -> bool {
-    matches!(class, RLE | LRE | RLO | LRO | PDF | BN)
+    BidiClassSet::REMOVED_BY_X9.contains(class)
 }
 
 // For use as a predicate for `position` / `rposition`
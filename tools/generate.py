@@ -20,7 +20,9 @@ DATA_DIR = 'data/ucd'
 TESTS_DATA_DIR = 'tests/data'
 README_NAME = "ReadMe.txt"
 UNICODE_DATA_NAME = "UnicodeData.txt"
+BIDI_MIRRORING_NAME = "BidiMirroring.txt"
 TABLES_PATH = os.path.join("src", "char_data", "tables.rs")
+MIRRORING_PATH = os.path.join("src", "char_data", "mirroring.rs")
 
 PREAMBLE = '''// NOTE:
 // The following code was generated by "tools/generate.py". do not edit directly
@@ -197,6 +199,33 @@ use self::BidiClass::*;
         pfun=lambda x: "(%s,%s,%s)" % (escape_char(x[0]), escape_char(x[1]), x[2]),
     )
 
+def load_mirroring_data():
+    fetch_data(BIDI_MIRRORING_NAME)
+    pairs = []
+    for line in fileinput.input(os.path.join(DATA_DIR, BIDI_MIRRORING_NAME)):
+        data = line.split('#')[0].split(';')
+        if len(data) != 2:
+            continue
+        pairs.append((int(data[0], 16), int(data[1], 16)))
+    pairs.sort(key=lambda w: w[0])
+    return pairs
+
+def emit_mirroring_module(file_, pairs, unicode_version):
+    file_.write("""// NOTE:
+// The following code was generated by "tools/generate.py". do not edit directly
+//
+// It was generated from BidiMirroring.txt of the Unicode Character Database, version %s.%s.%s.
+//
+// Each entry maps a character to the character whose glyph is its mirror image, sorted by the
+// first character.  Characters that are mirrored without such a pair are not listed.
+
+pub const BIDI_MIRRORING_GLYPH: &[(char, char)] = &[
+""" % unicode_version)
+    entries = ["('\\u{%X}', '\\u{%X}')" % pair for pair in pairs]
+    for i in range(0, len(entries), 3):
+        file_.write("  " + ", ".join(entries[i:i + 3]) + ",\n")
+    file_.write("];\n")
+
 def get_unicode_version():
     fetch_data(README_NAME)
     with open_data(README_NAME) as readme:
@@ -222,6 +251,10 @@ pub const UNICODE_VERSION: (u64, u64, u64) = (%s, %s, %s);
         (bidi_categories, bidi_class_table) = load_unicode_data()
         emit_bidi_module(file_, bidi_class_table, bidi_categories)
 
+    # Build the mirroring table
+    with open(MIRRORING_PATH, "w") as file_:
+        emit_mirroring_module(file_, load_mirroring_data(), unicode_version)
+
     # Fetch test data files
     fetch_test_data("BidiTest.txt")
     fetch_test_data("BidiCharacterTest.txt")